@@ -283,6 +283,7 @@ mod tests {
                 storages: vec![],
                 archive_format: ArchiveFormat::TarBzip2,
                 snapshot_version: SnapshotVersion::default(),
+                snapshot_window: None,
             };
 
             AccountsHashVerifier::process_accounts_package(
@@ -43,6 +43,7 @@ use solana_rpc::{
 };
 use solana_runtime::{
     accounts::AccountAddressFilter,
+    accounts_db::AccountFilter,
     accounts_index::{AccountIndex, AccountSecondaryIndexes, IndexKey},
     bank::Bank,
     bank_forks::{BankForks, SnapshotConfig},
@@ -1738,11 +1739,9 @@ impl JsonRpcRequestProcessor {
         program_id: &Pubkey,
         filters: Vec<RpcFilterType>,
     ) -> Result<Vec<(Pubkey, AccountSharedData)>> {
-        let filter_closure = |account: &AccountSharedData| {
-            filters.iter().all(|filter_type| match filter_type {
-                RpcFilterType::DataSize(size) => account.data().len() as u64 == *size,
-                RpcFilterType::Memcmp(compare) => compare.bytes_match(&account.data()),
-            })
+        let account_filters = match account_filters(&filters) {
+            Some(account_filters) => account_filters,
+            None => return Ok(vec![]),
         };
         if self
             .config
@@ -1755,18 +1754,17 @@ impl JsonRpcRequestProcessor {
                 }
                 .into());
             }
-            Ok(
-                bank.get_filtered_indexed_accounts(&IndexKey::ProgramId(*program_id), |account| {
-                    // The program-id account index checks for Account owner on inclusion. However, due
-                    // to the current AccountsDb implementation, an account may remain in storage as a
-                    // zero-lamport AccountSharedData::Default() after being wiped and reinitialized in later
-                    // updates. We include the redundant filters here to avoid returning these
-                    // accounts.
-                    account.owner() == program_id && filter_closure(account)
-                }),
-            )
+            // The program-id account index checks for Account owner on inclusion. However, due
+            // to the current AccountsDb implementation, an account may remain in storage as a
+            // zero-lamport AccountSharedData::Default() after being wiped and reinitialized in later
+            // updates. We include the redundant filters here to avoid returning these
+            // accounts.
+            let mut account_filters = account_filters;
+            account_filters.insert(0, AccountFilter::Owner(*program_id));
+            Ok(bank
+                .get_indexed_accounts_matching(&IndexKey::ProgramId(*program_id), &account_filters))
         } else {
-            Ok(bank.get_filtered_program_accounts(program_id, filter_closure))
+            Ok(bank.get_program_accounts_matching(program_id, &account_filters))
         }
     }
 
@@ -1804,15 +1802,14 @@ impl JsonRpcRequestProcessor {
                 }
                 .into());
             }
-            Ok(bank.get_filtered_indexed_accounts(
+            let mut account_filters = match account_filters(&filters) {
+                Some(account_filters) => account_filters,
+                None => return Ok(vec![]),
+            };
+            account_filters.insert(0, AccountFilter::Owner(spl_token_id_v2_0()));
+            Ok(bank.get_indexed_accounts_matching(
                 &IndexKey::SplTokenOwner(*owner_key),
-                |account| {
-                    account.owner() == &spl_token_id_v2_0()
-                        && filters.iter().all(|filter_type| match filter_type {
-                            RpcFilterType::DataSize(size) => account.data().len() as u64 == *size,
-                            RpcFilterType::Memcmp(compare) => compare.bytes_match(&account.data()),
-                        })
-                },
+                &account_filters,
             ))
         } else {
             self.get_filtered_program_accounts(bank, &spl_token_id_v2_0(), filters)
@@ -1852,15 +1849,15 @@ impl JsonRpcRequestProcessor {
                 }
                 .into());
             }
-            Ok(
-                bank.get_filtered_indexed_accounts(&IndexKey::SplTokenMint(*mint_key), |account| {
-                    account.owner() == &spl_token_id_v2_0()
-                        && filters.iter().all(|filter_type| match filter_type {
-                            RpcFilterType::DataSize(size) => account.data().len() as u64 == *size,
-                            RpcFilterType::Memcmp(compare) => compare.bytes_match(&account.data()),
-                        })
-                }),
-            )
+            let mut account_filters = match account_filters(&filters) {
+                Some(account_filters) => account_filters,
+                None => return Ok(vec![]),
+            };
+            account_filters.insert(0, AccountFilter::Owner(spl_token_id_v2_0()));
+            Ok(bank.get_indexed_accounts_matching(
+                &IndexKey::SplTokenMint(*mint_key),
+                &account_filters,
+            ))
         } else {
             self.get_filtered_program_accounts(bank, &spl_token_id_v2_0(), filters)
         }
@@ -1885,6 +1882,31 @@ fn verify_filter(input: &RpcFilterType) -> Result<()> {
         .map_err(|e| Error::invalid_params(format!("Invalid param: {:?}", e)))
 }
 
+/// Translate RPC filters into `AccountFilter`s, which the accounts scan checks before loading
+/// account data. Returns `None` if some filter can never match.
+fn account_filters(filters: &[RpcFilterType]) -> Option<Vec<AccountFilter>> {
+    filters
+        .iter()
+        .map(|filter_type| match filter_type {
+            RpcFilterType::DataSize(size) => {
+                let size = *size as usize;
+                Some(AccountFilter::DataLen(size..=size))
+            }
+            RpcFilterType::Memcmp(compare) => match &compare.bytes {
+                MemcmpEncodedBytes::Binary(bytes) => {
+                    bs58::decode(bytes)
+                        .into_vec()
+                        .ok()
+                        .map(|bytes| AccountFilter::DataMatches {
+                            offset: compare.offset,
+                            bytes,
+                        })
+                }
+            },
+        })
+        .collect()
+}
+
 fn verify_pubkey(input: &str) -> Result<Pubkey> {
     input
         .parse()
@@ -55,6 +55,7 @@ use solana_runtime::{
     bank_forks::{BankForks, SnapshotConfig},
     commitment::BlockCommitmentCache,
     hardened_unpack::{open_genesis_config, MAX_GENESIS_ARCHIVE_UNPACKED_SIZE},
+    snapshot_utils::StorageManifest,
 };
 use solana_sdk::{
     clock::Slot,
@@ -137,6 +138,9 @@ pub struct ValidatorConfig {
     pub tpu_coalesce_ms: u64,
     pub validator_exit: Arc<RwLock<ValidatorExit>>,
     pub no_wait_for_vote_to_start_leader: bool,
+    /// Check the files of the snapshot booted from against this manifest, signed by the
+    /// given trusted key
+    pub snapshot_storage_manifest: Option<(StorageManifest, Pubkey)>,
 }
 
 impl Default for ValidatorConfig {
@@ -193,6 +197,7 @@ impl Default for ValidatorConfig {
             tpu_coalesce_ms: DEFAULT_TPU_COALESCE_MS,
             validator_exit: Arc::new(RwLock::new(ValidatorExit::default())),
             no_wait_for_vote_to_start_leader: true,
+            snapshot_storage_manifest: None,
         }
     }
 }
@@ -1119,6 +1124,7 @@ fn new_banks_from_ledger(
         debug_keys: config.debug_keys.clone(),
         account_indexes: config.account_indexes.clone(),
         accounts_db_caching_enabled: config.accounts_db_caching_enabled,
+        snapshot_storage_manifest: config.snapshot_storage_manifest.clone(),
         ..blockstore_processor::ProcessOptions::default()
     };
 
@@ -145,7 +145,7 @@ mod tests {
 
         let old_last_bank = old_bank_forks.get(old_last_slot).unwrap();
 
-        let deserialized_bank = snapshot_utils::bank_from_archive(
+        let (deserialized_bank, _generate_index_report) = snapshot_utils::bank_from_archive(
             &account_paths,
             &[],
             &old_bank_forks
@@ -165,6 +165,8 @@ mod tests {
             AccountSecondaryIndexes::default(),
             false,
             None,
+            None,
+            false,
         )
         .unwrap();
 
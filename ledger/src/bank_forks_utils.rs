@@ -130,7 +130,7 @@ fn load_from_snapshot(
         process::exit(1);
     }
 
-    let deserialized_bank = snapshot_utils::bank_from_archive(
+    let (deserialized_bank, generate_index_report) = snapshot_utils::bank_from_archive(
         &account_paths,
         &process_options.frozen_accounts,
         &snapshot_config.snapshot_path,
@@ -142,8 +142,31 @@ fn load_from_snapshot(
         process_options.account_indexes.clone(),
         process_options.accounts_db_caching_enabled,
         process_options.limit_load_slot_count_from_snapshot,
+        process_options
+            .snapshot_storage_manifest
+            .as_ref()
+            .map(|(storage_manifest, trusted_signer)| (storage_manifest, trusted_signer)),
+        process_options.accounts_db_verify_storage_hashes,
     )
     .expect("Load from snapshot failed");
+    datapoint_info!(
+        "load_from_snapshot-generate_index",
+        ("slots", generate_index_report.num_slots(), i64),
+        ("stores", generate_index_report.num_stores(), i64),
+        ("accounts", generate_index_report.num_accounts, i64),
+        ("duplicates", generate_index_report.num_duplicates, i64),
+        (
+            "zero_lamport_accounts",
+            generate_index_report.num_zero_lamport_accounts,
+            i64
+        ),
+        ("bytes", generate_index_report.total_bytes, i64),
+        (
+            "slots_over_thresholds",
+            generate_index_report.slots_over_thresholds.len(),
+            i64
+        ),
+    );
     if let Some(shrink_paths) = shrink_paths {
         deserialized_bank.set_shrink_paths(shrink_paths);
     }
@@ -24,6 +24,7 @@ use solana_runtime::{
     bank_forks::BankForks,
     bank_utils,
     commitment::VOTE_THRESHOLD_SIZE,
+    snapshot_utils::StorageManifest,
     transaction_batch::TransactionBatch,
     vote_account::ArcVoteAccount,
     vote_sender_types::ReplayVoteSender,
@@ -373,6 +374,10 @@ pub struct ProcessOptions {
     pub limit_load_slot_count_from_snapshot: Option<usize>,
     pub allow_dead_slots: bool,
     pub accounts_db_test_hash_calculation: bool,
+    /// Check the hash stored with every account of the snapshot's storage files on load
+    pub accounts_db_verify_storage_hashes: bool,
+    /// Check the snapshot's files against this manifest, signed by the given trusted key
+    pub snapshot_storage_manifest: Option<(StorageManifest, Pubkey)>,
 }
 
 pub fn process_blockstore(
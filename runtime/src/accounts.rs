@@ -1,6 +1,7 @@
 use crate::{
     accounts_db::{
-        AccountsDb, BankHashInfo, ErrorCounters, LoadHint, LoadedAccount, ScanStorageResult,
+        AccountFilter, AccountsDb, BankHashInfo, ErrorCounters, IndexScanError, IndexScanOptions,
+        LoadHint, LoadedAccount, ScanStorageResult,
     },
     accounts_index::{AccountSecondaryIndexes, IndexKey},
     ancestors::Ancestors,
@@ -133,12 +134,10 @@ impl Accounts {
         caching_enabled: bool,
     ) -> Self {
         Self {
-            accounts_db: Arc::new(AccountsDb::new_with_config(
-                paths,
-                cluster_type,
-                account_indexes,
-                caching_enabled,
-            )),
+            accounts_db: Arc::new(
+                AccountsDb::new_with_config(paths, cluster_type, account_indexes, caching_enabled)
+                    .unwrap_or_else(|err| panic!("{}", err)),
+            ),
             account_locks: Mutex::new(AccountLocks::default()),
         }
     }
@@ -703,6 +702,63 @@ impl Accounts {
         )
     }
 
+    /// Like `load_by_program_with_filter()`, but `filters` are checked against the stored
+    /// account, so non-matching accounts are skipped without copying their data
+    pub fn load_by_program_matching(
+        &self,
+        ancestors: &Ancestors,
+        program_id: &Pubkey,
+        filters: &[AccountFilter],
+    ) -> Vec<(Pubkey, AccountSharedData)> {
+        let mut filters = filters.to_vec();
+        filters.insert(0, AccountFilter::Owner(*program_id));
+        self.accounts_db.scan_accounts_filtered(
+            ancestors,
+            &filters,
+            |collector: &mut Vec<(Pubkey, AccountSharedData)>, some_account_tuple| {
+                Self::load_while_filtering(collector, some_account_tuple, |_| true)
+            },
+        )
+    }
+
+    pub fn load_by_index_key_matching(
+        &self,
+        ancestors: &Ancestors,
+        index_key: &IndexKey,
+        filters: &[AccountFilter],
+    ) -> Vec<(Pubkey, AccountSharedData)> {
+        self.accounts_db
+            .index_scan_accounts_filtered(
+                ancestors,
+                *index_key,
+                filters,
+                |collector: &mut Vec<(Pubkey, AccountSharedData)>, some_account_tuple| {
+                    Self::load_while_filtering(collector, some_account_tuple, |_| true)
+                },
+            )
+            .0
+    }
+
+    pub fn load_by_index_key_with_options(
+        &self,
+        ancestors: &Ancestors,
+        index_key: &IndexKey,
+        filters: &[AccountFilter],
+        options: IndexScanOptions,
+    ) -> std::result::Result<Vec<(Pubkey, AccountSharedData)>, IndexScanError> {
+        self.accounts_db
+            .index_scan_accounts_with_options(
+                ancestors,
+                *index_key,
+                filters,
+                options,
+                |collector: &mut Vec<(Pubkey, AccountSharedData)>, some_account_tuple| {
+                    Self::load_while_filtering(collector, some_account_tuple, |_| true)
+                },
+            )
+            .map(|(accounts, _used_index)| accounts)
+    }
+
     pub fn load_by_index_key_with_filter<F: Fn(&AccountSharedData) -> bool>(
         &self,
         ancestors: &Ancestors,
@@ -828,11 +884,10 @@ impl Accounts {
 
     pub fn bank_hash_info_at(&self, slot: Slot) -> BankHashInfo {
         let delta_hash = self.accounts_db.get_accounts_delta_hash(slot);
-        let bank_hashes = self.accounts_db.bank_hashes.read().unwrap();
-        let mut hash_info = bank_hashes
-            .get(&slot)
-            .expect("No bank hash was found for this bank, that should not be possible")
-            .clone();
+        let mut hash_info = self
+            .accounts_db
+            .bank_hash_info(slot)
+            .expect("No bank hash was found for this bank, that should not be possible");
         hash_info.hash = delta_hash;
         hash_info
     }
@@ -3,6 +3,7 @@
 // This can be expensive since we have to walk the append vecs being cleaned up.
 
 use crate::{
+    accounts_db::CleanPhase,
     bank::{Bank, BankSlotDelta, DropCallback},
     bank_forks::{BankForks, SnapshotConfig},
     snapshot_package::AccountsPackageSender,
@@ -29,10 +30,13 @@ const SHRUNKEN_ACCOUNT_PER_SEC: usize = 250;
 const SHRUNKEN_ACCOUNT_PER_INTERVAL: usize =
     SHRUNKEN_ACCOUNT_PER_SEC / (1000 / INTERVAL_MS as usize);
 const CLEAN_INTERVAL_BLOCKS: u64 = 100;
+// Candidates scanned per iteration by a clean in progress, so that a large clean doesn't hold
+// up flushing the accounts cache for seconds
+const CLEAN_KEYS_PER_INTERVAL: usize = 200_000;
 
 // This value is chosen to spread the dropping cost over 3 expiration checks
 // RecycleStores are fully populated almost all of its lifetime. So, otherwise
-// this would drop MAX_RECYCLE_STORES mmaps at once in the worst case...
+// this would drop the whole recycle budget of mmaps at once in the worst case...
 // (Anyway, the dropping part is outside the AccountsDb::recycle_stores lock
 // and dropped in this AccountsBackgroundServe, so this shouldn't matter much)
 const RECYCLE_STORE_EXPIRATION_INTERVAL_SECS: u64 = crate::accounts_db::EXPIRATION_TTL_SECONDS / 3;
@@ -107,8 +111,10 @@ impl SnapshotRequestHandler {
                     Hash::default()
                 };
 
+                let shrink_v2_enabled =
+                    snapshot_root_bank.rc.accounts.accounts_db.shrink_v2_enabled;
                 let mut shrink_time = Measure::start("shrink_time");
-                if !accounts_db_caching_enabled {
+                if !shrink_v2_enabled {
                     snapshot_root_bank
                         .process_stale_slot_with_budget(0, SHRUNKEN_ACCOUNT_PER_INTERVAL);
                 }
@@ -157,7 +163,7 @@ impl SnapshotRequestHandler {
                 snapshot_root_bank.clean_accounts(true, false);
                 clean_time.stop();
 
-                if accounts_db_caching_enabled {
+                if shrink_v2_enabled {
                     shrink_time = Measure::start("shrink_time");
                     snapshot_root_bank.shrink_candidate_slots();
                     shrink_time.stop();
@@ -292,6 +298,7 @@ impl AccountsBackgroundService {
         let exit = exit.clone();
         let mut consumed_budget = 0;
         let mut last_cleaned_block_height = 0;
+        let mut clean_session = None;
         let mut removed_slots_count = 0;
         let mut total_remove_slots_time = 0;
         let mut last_expiration_check_time = Instant::now();
@@ -350,7 +357,7 @@ impl AccountsBackgroundService {
                     assert!(last_cleaned_block_height <= snapshot_block_height);
                     last_cleaned_block_height = snapshot_block_height;
                 } else {
-                    if accounts_db_caching_enabled {
+                    if bank.rc.accounts.accounts_db.shrink_v2_enabled {
                         bank.shrink_candidate_slots();
                     } else {
                         // under sustained writes, shrink can lag behind so cap to
@@ -363,8 +370,22 @@ impl AccountsBackgroundService {
                             )
                             .min(SHRUNKEN_ACCOUNT_PER_INTERVAL);
                     }
-                    if bank.block_height() - last_cleaned_block_height
+                    if let Some(session) = clean_session.as_mut() {
+                        // the session cleans up to the root it was started at, whichever
+                        // bank carries it on
+                        if bank.clean_step(session, CLEAN_KEYS_PER_INTERVAL) == CleanPhase::Done {
+                            clean_session = None;
+                        }
+                    } else if bank.block_height() - last_cleaned_block_height
                         > (CLEAN_INTERVAL_BLOCKS + thread_rng().gen_range(0, 10))
+                        // too many pubkeys are waiting for a clean, so don't wait for the
+                        // interval, but for a new block at least
+                        || (bank.block_height() > last_cleaned_block_height
+                            && bank
+                                .rc
+                                .accounts
+                                .accounts_db
+                                .uncleaned_pubkeys_over_cap())
                     {
                         if accounts_db_caching_enabled {
                             // Note that the flush will do an internal clean of the
@@ -373,7 +394,21 @@ impl AccountsBackgroundService {
                             // slots >= bank.slot()
                             bank.force_flush_accounts_cache();
                         }
-                        bank.clean_accounts(true, false);
+                        // slots below the root that aren't roots belong to abandoned forks
+                        bank.rc
+                            .accounts
+                            .accounts_db
+                            .prune_uncleaned_metadata(bank.slot());
+                        // nothing to clean is the common case on an idle cluster, so don't
+                        // hold shrink off the candidate slots for nothing
+                        if bank.clean_needed(true).is_needed() {
+                            let mut session = bank.start_clean_session(true);
+                            if bank.clean_step(&mut session, CLEAN_KEYS_PER_INTERVAL)
+                                != CleanPhase::Done
+                            {
+                                clean_session = Some(session);
+                            }
+                        }
                         last_cleaned_block_height = bank.block_height();
                     }
                 }
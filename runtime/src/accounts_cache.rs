@@ -1,6 +1,7 @@
 use dashmap::DashMap;
+use log::*;
 use solana_sdk::{
-    account::{AccountSharedData, ReadableAccount},
+    account::{Account, AccountSharedData, ReadableAccount},
     clock::Slot,
     hash::Hash,
     pubkey::Pubkey,
@@ -8,7 +9,10 @@ use solana_sdk::{
 use std::{
     borrow::Borrow,
     collections::BTreeSet,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter},
     ops::Deref,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, AtomicU64, Ordering},
         Arc, RwLock,
@@ -23,9 +27,21 @@ pub struct SlotCacheInner {
     same_account_writes: AtomicU64,
     same_account_writes_size: AtomicU64,
     unique_account_writes_size: AtomicU64,
+    // Writes superseded by a later write of the same pubkey in the same batch, which
+    // never made it into `cache`
+    combined_writes: AtomicU64,
     is_frozen: AtomicBool,
+    // Set when the contents of `cache` have been written out to this file to relieve
+    // memory pressure. The entries are reloaded the next time the slot is accessed.
+    spill_path: RwLock<Option<PathBuf>>,
 }
 
+// On-disk representation of a single spilled cache entry
+type SpilledAccount = (Pubkey, Account, Option<Hash>);
+// On-disk representation of a spilled slot: the slot, its same and unique account writes
+// sizes, which don't count towards the cache size while it's spilled, and its entries
+type SpilledSlot = (Slot, u64, u64, Vec<SpilledAccount>);
+
 impl SlotCacheInner {
     pub fn report_slot_store_metrics(&self) {
         datapoint_info!(
@@ -44,6 +60,11 @@ impl SlotCacheInner {
                 "unique_account_writes_size",
                 self.unique_account_writes_size.load(Ordering::Relaxed),
                 i64
+            ),
+            (
+                "combined_writes",
+                self.combined_writes.load(Ordering::Relaxed),
+                i64
             )
         );
     }
@@ -73,7 +94,21 @@ impl SlotCacheInner {
         item
     }
 
+    pub fn add_combined_writes(&self, count: u64) {
+        self.combined_writes.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn combined_writes(&self) -> u64 {
+        self.combined_writes.load(Ordering::Relaxed)
+    }
+
+    /// The cached `pubkey`. None as well if the slot is spilled and can't be reloaded, see
+    /// `AccountsCache::slot_cache()`.
     pub fn get_cloned(&self, pubkey: &Pubkey) -> Option<CachedAccount> {
+        if let Err(err) = self.unspill() {
+            error!("Unable to reload spilled cache entries: {}", err);
+            return None;
+        }
         self.cache
             .get(pubkey)
             // 1) Maybe can eventually use a Cow to avoid a clone on every read
@@ -94,6 +129,128 @@ impl SlotCacheInner {
         self.unique_account_writes_size.load(Ordering::Relaxed)
             + self.same_account_writes_size.load(Ordering::Relaxed)
     }
+
+    pub fn is_spilled(&self) -> bool {
+        self.spill_path.read().unwrap().is_some()
+    }
+
+    // Writes all the cached accounts to `path` and drops them from memory. The entries
+    // remain virtual cache entries, so the index doesn't need to be updated. Only safe
+    // to call on frozen slots, as any concurrent insert could otherwise be lost.
+    // Returns the number of accounts spilled.
+    fn spill(&self, slot: Slot, path: PathBuf) -> io::Result<usize> {
+        assert!(self.is_frozen());
+        let mut spill_path = self.spill_path.write().unwrap();
+        if spill_path.is_some() || self.cache.is_empty() {
+            return Ok(0);
+        }
+        let entries: Vec<SpilledAccount> = self
+            .cache
+            .iter()
+            .map(|item| {
+                let cached_account = item.value();
+                (
+                    *item.key(),
+                    Account::from(cached_account.account.clone()),
+                    *cached_account.hash.read().unwrap(),
+                )
+            })
+            .collect();
+        let same_account_writes_size = self.same_account_writes_size.load(Ordering::Relaxed);
+        let unique_account_writes_size = self.unique_account_writes_size.load(Ordering::Relaxed);
+        let mut writer = BufWriter::new(File::create(&path)?);
+        if let Err(err) = bincode::serialize_into(
+            &mut writer,
+            &(
+                slot,
+                same_account_writes_size,
+                unique_account_writes_size,
+                &entries,
+            ),
+        ) {
+            let _ = fs::remove_file(&path);
+            return Err(io::Error::new(io::ErrorKind::Other, err));
+        }
+        if let Err(err) = writer.into_inner() {
+            let _ = fs::remove_file(&path);
+            return Err(err.into_error());
+        }
+        self.cache.clear();
+        // the slot no longer takes any memory
+        self.same_account_writes_size.store(0, Ordering::Relaxed);
+        self.unique_account_writes_size.store(0, Ordering::Relaxed);
+        *spill_path = Some(path);
+        Ok(entries.len())
+    }
+
+    /// Calls `f` with every account in the slot. The accounts of a spilled slot are read from
+    /// its file without loading them back into memory. Returns the number of accounts.
+    pub fn scan(&self, mut f: impl FnMut(&Pubkey, &AccountSharedData)) -> io::Result<usize> {
+        // keeps the slot from being spilled or unspilled meanwhile
+        let spill_path = self.spill_path.read().unwrap();
+        match &*spill_path {
+            Some(path) => {
+                let (_slot, _same_size, _unique_size, entries): SpilledSlot =
+                    bincode::deserialize_from(BufReader::new(File::open(path)?))
+                        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                for (pubkey, account, _hash) in &entries {
+                    f(pubkey, &AccountSharedData::from(account.clone()));
+                }
+                Ok(entries.len())
+            }
+            None => {
+                let mut num_accounts = 0;
+                for item in self.cache.iter() {
+                    f(item.key(), &item.value().account);
+                    num_accounts += 1;
+                }
+                Ok(num_accounts)
+            }
+        }
+    }
+
+    // Loads the accounts back into memory if this slot was previously spilled. If the spill
+    // file can't be read, the slot stays spilled and the error is returned.
+    fn unspill(&self) -> io::Result<()> {
+        if !self.is_spilled() {
+            return Ok(());
+        }
+        let mut spill_path = self.spill_path.write().unwrap();
+        if let Some(path) = spill_path.as_ref() {
+            let read_error =
+                |err| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}: {}", path, err));
+            let file = File::open(path).map_err(|err| read_error(err.to_string()))?;
+            let (slot, same_account_writes_size, unique_account_writes_size, entries): SpilledSlot =
+                bincode::deserialize_from(BufReader::new(file))
+                    .map_err(|err| read_error(err.to_string()))?;
+            for (pubkey, account, hash) in entries {
+                self.cache.insert(
+                    pubkey,
+                    Arc::new(CachedAccountInner {
+                        account: AccountSharedData::from(account),
+                        hash: RwLock::new(hash),
+                        slot,
+                        pubkey,
+                    }),
+                );
+            }
+            self.same_account_writes_size
+                .store(same_account_writes_size, Ordering::Relaxed);
+            self.unique_account_writes_size
+                .store(unique_account_writes_size, Ordering::Relaxed);
+            let _ = fs::remove_file(path);
+            *spill_path = None;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for SlotCacheInner {
+    fn drop(&mut self) {
+        if let Some(path) = self.spill_path.get_mut().unwrap().take() {
+            let _ = fs::remove_file(path);
+        }
+    }
 }
 
 impl Deref for SlotCacheInner {
@@ -114,6 +271,16 @@ pub struct CachedAccountInner {
 }
 
 impl CachedAccountInner {
+    /// A cached account outside of any slot cache, its hash is computed on first use
+    pub(crate) fn new(pubkey: &Pubkey, account: AccountSharedData, slot: Slot) -> CachedAccount {
+        Arc::new(CachedAccountInner {
+            account,
+            hash: RwLock::new(None),
+            slot,
+            pubkey: *pubkey,
+        })
+    }
+
     pub fn hash(&self) -> Hash {
         let hash = self.hash.read().unwrap();
         match *hash {
@@ -192,13 +359,60 @@ impl AccountsCache {
     }
 
     pub fn remove_slot(&self, slot: Slot) -> Option<SlotCache> {
-        self.cache.remove(&slot).map(|(_, slot_cache)| slot_cache)
+        self.cache.remove(&slot).map(|(_, slot_cache)| {
+            Self::unspill(slot, &slot_cache);
+            slot_cache
+        })
     }
 
+    /// The cache of `slot`, reloaded if it was spilled. A spilled slot whose file can't be
+    /// read is returned empty, so its accounts read as missing rather than take the node down.
+    /// Only unrooted slots older than the latest flushed root are spilled, which are never
+    /// rooted, so loads go on to the versions of other slots and in storage.
     pub fn slot_cache(&self, slot: Slot) -> Option<SlotCache> {
+        self.cache.get(&slot).map(|result| {
+            let slot_cache = result.value().clone();
+            Self::unspill(slot, &slot_cache);
+            slot_cache
+        })
+    }
+
+    fn unspill(slot: Slot, slot_cache: &SlotCacheInner) {
+        if let Err(err) = slot_cache.unspill() {
+            error!("Unable to reload spilled cache slot {}: {}", slot, err);
+        }
+    }
+
+    /// Like `slot_cache()`, but leaves a spilled slot on disk
+    pub fn peek_slot_cache(&self, slot: Slot) -> Option<SlotCache> {
         self.cache.get(&slot).map(|result| result.value().clone())
     }
 
+    // Moves the contents of a frozen slot out of memory into a file under `spill_dir`.
+    // Skipped if anybody else holds a reference to the slot's cache, since they may be
+    // iterating over it. The entries are transparently reloaded on the next access.
+    pub fn spill_slot(&self, slot: Slot, spill_dir: &Path) -> io::Result<usize> {
+        // Holding the write guard prevents anyone from grabbing a new reference
+        // to this slot's cache while it's being spilled
+        match self.cache.get_mut(&slot) {
+            Some(mut entry) => {
+                let slot_cache = entry.value_mut();
+                if Arc::strong_count(slot_cache) > 1 || !slot_cache.is_frozen() {
+                    return Ok(0);
+                }
+                slot_cache.spill(slot, spill_dir.join(format!("{}.spill", slot)))
+            }
+            None => Ok(0),
+        }
+    }
+
+    pub fn num_spilled_slots(&self) -> usize {
+        self.cache
+            .iter()
+            .filter(|item| item.value().is_spilled())
+            .count()
+    }
+
     pub fn add_root(&self, root: Slot) {
         let max_flushed_root = self.fetch_max_flush_root();
         if root > max_flushed_root || (root == max_flushed_root && root == 0) {
@@ -228,6 +442,7 @@ impl AccountsCache {
         self.cache.retain(|slot, slot_cache| {
             let should_remove = *slot <= max_root;
             if should_remove {
+                Self::unspill(*slot, slot_cache);
                 removed_slots.push((*slot, slot_cache.clone()))
             }
             !should_remove
@@ -261,6 +476,10 @@ impl AccountsCache {
         self.cache.len()
     }
 
+    pub fn slots(&self) -> Vec<Slot> {
+        self.cache.iter().map(|item| *item.key()).collect()
+    }
+
     pub fn fetch_max_flush_root(&self) -> Slot {
         self.max_flushed_root.load(Ordering::Relaxed)
     }
@@ -315,4 +534,81 @@ pub mod tests {
         // If the cache is told the size limit is 0, it should return the one frozen slot
         assert_eq!(cache.find_older_frozen_slots(0), vec![inserted_slot]);
     }
+
+    #[test]
+    fn test_spill_slot() {
+        let cache = AccountsCache::default();
+        let spill_dir = tempfile::TempDir::new().unwrap();
+        let slot = 1;
+        let pubkey = Pubkey::new_unique();
+        let account = AccountSharedData::new(1, 10, &Pubkey::new_unique());
+        cache.store(slot, &pubkey, account.clone(), Some(&Hash::new_unique()));
+        let hash = cache.load(slot, &pubkey).unwrap().hash();
+
+        // Unfrozen slots may still be written to, so they can't be spilled
+        assert_eq!(cache.spill_slot(slot, spill_dir.path()).unwrap(), 0);
+        cache.slot_cache(slot).unwrap().mark_slot_frozen();
+
+        // Slots referenced elsewhere can't be spilled either
+        let slot_cache = cache.slot_cache(slot).unwrap();
+        assert_eq!(cache.spill_slot(slot, spill_dir.path()).unwrap(), 0);
+        drop(slot_cache);
+
+        assert_eq!(cache.spill_slot(slot, spill_dir.path()).unwrap(), 1);
+        assert_eq!(cache.num_spilled_slots(), 1);
+        assert_eq!(cache.num_slots(), 1);
+
+        // Scanning reads the spilled accounts without reloading them
+        let mut scanned = vec![];
+        let slot_cache = cache.peek_slot_cache(slot).unwrap();
+        assert_eq!(
+            slot_cache
+                .scan(|pubkey, account| scanned.push((*pubkey, account.clone())))
+                .unwrap(),
+            1
+        );
+        drop(slot_cache);
+        assert_eq!(scanned, vec![(pubkey, account.clone())]);
+        assert_eq!(cache.num_spilled_slots(), 1);
+
+        // Accessing the slot reloads the spilled accounts
+        let cached_account = cache.load(slot, &pubkey).unwrap();
+        assert_eq!(cached_account.account, account);
+        assert_eq!(cached_account.hash(), hash);
+        assert_eq!(cache.num_spilled_slots(), 0);
+        assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 0);
+
+        // Dropping a spilled slot cleans up its spill file
+        assert_eq!(cache.spill_slot(slot, spill_dir.path()).unwrap(), 1);
+        assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 1);
+        cache.cache.remove(&slot);
+        assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_spill_slot_size_and_unreadable_file() {
+        let cache = AccountsCache::default();
+        let spill_dir = tempfile::TempDir::new().unwrap();
+        let slot = 1;
+        let pubkey = Pubkey::new_unique();
+        let account = AccountSharedData::new(1, 10, &Pubkey::new_unique());
+        cache.store(slot, &pubkey, account.clone(), Some(&Hash::new_unique()));
+        cache.store(slot, &pubkey, account.clone(), Some(&Hash::new_unique()));
+        cache.slot_cache(slot).unwrap().mark_slot_frozen();
+        assert_eq!(cache.peek_slot_cache(slot).unwrap().total_bytes(), 20);
+
+        // a spilled slot takes no memory until it's reloaded
+        assert_eq!(cache.spill_slot(slot, spill_dir.path()).unwrap(), 1);
+        assert_eq!(cache.peek_slot_cache(slot).unwrap().total_bytes(), 0);
+        assert_eq!(cache.slot_cache(slot).unwrap().total_bytes(), 20);
+
+        // an unreadable spill file leaves the slot spilled, and its accounts missing
+        assert_eq!(cache.spill_slot(slot, spill_dir.path()).unwrap(), 1);
+        std::fs::write(spill_dir.path().join(format!("{}.spill", slot)), b"garbage").unwrap();
+        assert!(cache.load(slot, &pubkey).is_none());
+        assert_eq!(cache.num_spilled_slots(), 1);
+        assert!(cache.slot_cache(slot).unwrap().is_empty());
+        assert!(cache.remove_slot(slot).is_some());
+        assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 0);
+    }
 }
@@ -1,12 +1,23 @@
+use crate::accounts_db::AccountsDb;
 use log::*;
 use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 use solana_measure::measure::Measure;
 use solana_sdk::{
+    account::ReadableAccount,
     clock::Slot,
     hash::{Hash, Hasher},
     pubkey::Pubkey,
 };
-use std::{convert::TryInto, sync::Mutex};
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+    convert::TryInto,
+    fs::File,
+    io::{self, BufReader, BufWriter, Write},
+    path::PathBuf,
+    sync::{Arc, Mutex, RwLock},
+};
 
 pub const ZERO_RAW_LAMPORTS_SENTINEL: u64 = std::u64::MAX;
 pub const MERKLE_FANOUT: usize = 16;
@@ -28,6 +39,12 @@ pub struct HashStats {
     pub hash_total: usize,
     pub unreduced_entries: usize,
     pub num_snapshot_storage: usize,
+    pub storage_cache_hits: usize,
+    pub storage_cache_misses: usize,
+    pub num_scan_passes: usize,
+    pub num_bins: usize,
+    pub num_scan_chunks: usize,
+    pub num_spilled_runs: usize,
 }
 impl HashStats {
     fn log(&mut self) {
@@ -50,12 +67,22 @@ impl HashStats {
                 self.num_snapshot_storage as i64,
                 i64
             ),
+            ("storage_cache_hits", self.storage_cache_hits as i64, i64),
+            (
+                "storage_cache_misses",
+                self.storage_cache_misses as i64,
+                i64
+            ),
+            ("num_scan_passes", self.num_scan_passes as i64, i64),
+            ("num_bins", self.num_bins as i64, i64),
+            ("num_scan_chunks", self.num_scan_chunks as i64, i64),
+            ("num_spilled_runs", self.num_spilled_runs as i64, i64),
             ("total", total_time_us as i64, i64),
         );
     }
 }
 
-#[derive(Default, Debug, PartialEq, Clone)]
+#[derive(Default, Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct CalculateHashIntermediate {
     pub version: u64,
     pub hash: Hash,
@@ -76,6 +103,193 @@ impl CalculateHashIntermediate {
     }
 }
 
+/// The hashes of a level of the accounts hash merkle tree that are hashed together into a
+/// node of the next level, and which of them is on the path being proven
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct MerkleProofLevel {
+    pub hashes: Vec<Hash>,
+    pub index: usize,
+}
+
+/// Proof that an account is in the accounts hash of a slot: the path through the merkle tree
+/// from the account's leaf to the root, a level per entry of `path`, starting at the leaves
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct AccountProof {
+    pub pubkey: Pubkey,
+    pub account_hash: Hash,
+    pub lamports: u64,
+    /// Slot the proven version of the account was stored in, which its hash covers
+    pub account_slot: Slot,
+    /// Position of the account among the `num_leaves` accounts hashed, in pubkey order
+    pub leaf_index: usize,
+    pub num_leaves: usize,
+    pub path: Vec<MerkleProofLevel>,
+    pub root: Hash,
+}
+
+impl AccountProof {
+    /// Whether `account` is the one proven: its hash, as of `account_slot`, has to be
+    /// `account_hash`, and hashing that up along `path` has to end up at `root`
+    pub fn verify(&self, account: &impl ReadableAccount) -> bool {
+        if account.lamports() != self.lamports
+            || AccountsDb::hash_account(self.account_slot, account, &self.pubkey)
+                != self.account_hash
+        {
+            return false;
+        }
+        let mut node = self.account_hash;
+        let mut index = self.leaf_index;
+        for level in &self.path {
+            if level.hashes.is_empty()
+                || level.hashes.len() > MERKLE_FANOUT
+                || level.index != index % MERKLE_FANOUT
+                || level.hashes.get(level.index) != Some(&node)
+            {
+                return false;
+            }
+            let mut hasher = Hasher::default();
+            level
+                .hashes
+                .iter()
+                .for_each(|hash| hasher.hash(hash.as_ref()));
+            node = hasher.result();
+            index /= MERKLE_FANOUT;
+        }
+        !self.path.is_empty()
+            && self.leaf_index < self.num_leaves
+            && index == 0
+            && node == self.root
+    }
+}
+
+/// A bin of intermediate data sorted by `AccountsHash::compare_two_hash_entries()` and
+/// written to a file, so that a bin doesn't have to be held in memory to be hashed
+#[derive(Debug)]
+pub struct SpilledRun {
+    path: PathBuf,
+    len: usize,
+}
+
+impl SpilledRun {
+    /// Sorts `items` and writes them to `path`
+    pub fn write(path: PathBuf, mut items: Vec<CalculateHashIntermediate>) -> io::Result<Self> {
+        items.sort_unstable_by(AccountsHash::compare_two_hash_entries);
+        let mut writer = BufWriter::new(File::create(&path)?);
+        for item in &items {
+            bincode::serialize_into(&mut writer, item)
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        }
+        writer.flush()?;
+        Ok(Self {
+            path,
+            len: items.len(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    fn reader(&self) -> io::Result<SpilledRunReader> {
+        Ok(SpilledRunReader {
+            reader: BufReader::new(File::open(&self.path)?),
+            remaining: self.len,
+        })
+    }
+}
+
+struct SpilledRunReader {
+    reader: BufReader<File>,
+    remaining: usize,
+}
+
+impl SpilledRunReader {
+    fn next(&mut self) -> io::Result<Option<CalculateHashIntermediate>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        self.remaining -= 1;
+        bincode::deserialize_from(&mut self.reader)
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
+
+// The head of a run being merged, ordered so that the max-heap pops the first entry
+struct MergeHead {
+    item: CalculateHashIntermediate,
+    run: usize,
+}
+
+impl Ord for MergeHead {
+    fn cmp(&self, other: &Self) -> Ordering {
+        AccountsHash::compare_two_hash_entries(&other.item, &self.item)
+            .then_with(|| other.run.cmp(&self.run))
+    }
+}
+
+impl PartialOrd for MergeHead {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for MergeHead {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for MergeHead {}
+
+/// (append vec id, generation, written bytes) of a storage whose scan results are cached.
+/// Appending to a storage changes its written bytes, recycling a storage gives it a new id and
+/// resetting it in place bumps its generation, so an entry can never be hit once the contents
+/// it was built from have changed.
+pub type StorageHashCacheKey = (usize, u64, usize);
+
+/// Scan results of individual storages, reused across accounts hash calculations.
+#[derive(Default, Debug)]
+pub struct StorageHashCache {
+    entries: RwLock<HashMap<StorageHashCacheKey, Arc<Vec<CalculateHashIntermediate>>>>,
+}
+
+impl StorageHashCache {
+    pub fn get(&self, key: &StorageHashCacheKey) -> Option<Arc<Vec<CalculateHashIntermediate>>> {
+        self.entries.read().unwrap().get(key).cloned()
+    }
+
+    pub fn insert(
+        &self,
+        key: StorageHashCacheKey,
+        items: Vec<CalculateHashIntermediate>,
+    ) -> Arc<Vec<CalculateHashIntermediate>> {
+        let items = Arc::new(items);
+        self.entries.write().unwrap().insert(key, items.clone());
+        items
+    }
+
+    /// Drop entries for storages that were not part of the latest calculation
+    pub fn retain(&self, keys: &HashSet<StorageHashCacheKey>) {
+        self.entries
+            .write()
+            .unwrap()
+            .retain(|key, _| keys.contains(key));
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct CumulativeOffset {
     pub index: Vec<usize>,
@@ -694,6 +908,108 @@ impl AccountsHash {
         (result, sum)
     }
 
+    /// The leaves of the accounts hash in pubkey order, as `rest_of_hash_calculation()` would
+    /// hash them, as the entries of the versions of the accounts they're the hashes of
+    pub fn de_duped_leaves(
+        data_sections_by_pubkey: Vec<Vec<Vec<CalculateHashIntermediate>>>,
+        stats: &mut HashStats,
+    ) -> Vec<CalculateHashIntermediate> {
+        let outer = Self::flatten_hash_intermediate(data_sections_by_pubkey, stats);
+        let sorted_data_by_pubkey = Self::sort_hash_intermediate(outer, stats);
+        sorted_data_by_pubkey
+            .into_par_iter()
+            .map(|pubkey_division| {
+                // entries are sorted so the current version of each pubkey comes first
+                let mut leaves = Vec::with_capacity(pubkey_division.len());
+                let mut last = None;
+                for now in pubkey_division {
+                    if last == Some(now.pubkey) {
+                        continue;
+                    }
+                    last = Some(now.pubkey);
+                    if now.lamports != ZERO_RAW_LAMPORTS_SENTINEL {
+                        leaves.push(now);
+                    }
+                }
+                leaves
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    /// The path from leaf `index` of `hashes` to the root that `compute_merkle_root_loop()`
+    /// calculates for them, see `AccountProof`
+    pub fn merkle_path(hashes: &[Hash], mut index: usize, fanout: usize) -> Vec<MerkleProofLevel> {
+        assert!(index < hashes.len());
+        let mut path = vec![];
+        let mut level = hashes.to_vec();
+        loop {
+            let start = index - index % fanout;
+            let end = std::cmp::min(start + fanout, level.len());
+            path.push(MerkleProofLevel {
+                hashes: level[start..end].to_vec(),
+                index: index - start,
+            });
+            if level.len() <= fanout {
+                return path;
+            }
+            level = level
+                .par_chunks(fanout)
+                .map(|chunk| {
+                    let mut hasher = Hasher::default();
+                    chunk.iter().for_each(|hash| hasher.hash(hash.as_ref()));
+                    hasher.result()
+                })
+                .collect();
+            index /= fanout;
+        }
+    }
+
+    // Merges the sorted runs of a bin like `de_dup_accounts_from_stores()` goes through a
+    // sorted bin, reading the runs as it goes
+    fn de_dup_spilled_runs(runs: &[SpilledRun]) -> io::Result<(Vec<Hash>, u128)> {
+        let mut readers = runs
+            .iter()
+            .map(|run| run.reader())
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut heads = BinaryHeap::with_capacity(readers.len());
+        for (run, reader) in readers.iter_mut().enumerate() {
+            if let Some(item) = reader.next()? {
+                heads.push(MergeHead { item, run });
+            }
+        }
+
+        let mut result = Vec::with_capacity(runs.iter().map(|run| run.len()).sum());
+        let mut sum: u128 = 0;
+        let mut prev: Option<CalculateHashIntermediate> = None;
+        while let Some(MergeHead { item: now, run }) = heads.pop() {
+            if let Some(item) = readers[run].next()? {
+                heads.push(MergeHead { item, run });
+            }
+            match &prev {
+                Some(prev) if prev.pubkey == now.pubkey => {
+                    assert!(
+                        !(prev.slot == now.slot
+                            && prev.version == now.version
+                            && (prev.hash != now.hash || prev.lamports != now.lamports)),
+                        "Conflicting store data. Pubkey: {}, Slot: {}, Version: {}, Hashes: {}, {}, Lamports: {}, {}", now.pubkey, now.slot, now.version, prev.hash, now.hash, prev.lamports, now.lamports
+                    );
+                }
+                _ => {
+                    // first entry for this key
+                    if now.lamports != ZERO_RAW_LAMPORTS_SENTINEL {
+                        result.push(now.hash);
+                        sum += now.lamports as u128;
+                    }
+                }
+            }
+            prev = Some(now);
+        }
+        Ok((result, sum))
+    }
+
     // input:
     // vec: unordered, created by parallelism
     //   vec: [0..bins] - where bins are pubkey ranges
@@ -703,15 +1019,72 @@ impl AccountsHash {
         data_sections_by_pubkey: Vec<Vec<Vec<CalculateHashIntermediate>>>,
         mut stats: &mut HashStats,
         is_last_pass: bool,
-        mut previous_state: PreviousPass,
+        previous_state: PreviousPass,
     ) -> (Hash, u64, PreviousPass) {
         let outer = Self::flatten_hash_intermediate(data_sections_by_pubkey, &mut stats);
 
         let sorted_data_by_pubkey = Self::sort_hash_intermediate(outer, &mut stats);
 
-        let (mut hashes, mut total_lamports) =
+        let (hashes, total_lamports) =
             Self::de_dup_and_eliminate_zeros(sorted_data_by_pubkey, &mut stats);
 
+        Self::hash_de_duped(hashes, total_lamports, stats, is_last_pass, previous_state)
+    }
+
+    /// Like `rest_of_hash_calculation()`, but the data of each bin is in sorted runs on disk,
+    /// which are merged a bin at a time rather than held in memory.
+    // input:
+    // vec: [0..bins] - where bins are pubkey ranges
+    //   vec: [..] - sorted runs of the containing bin
+    pub fn rest_of_hash_calculation_spilled(
+        runs_by_bin: Vec<Vec<SpilledRun>>,
+        stats: &mut HashStats,
+        is_last_pass: bool,
+        previous_state: PreviousPass,
+    ) -> io::Result<(Hash, u64, PreviousPass)> {
+        let mut merge_time = Measure::start("merge");
+        stats.num_spilled_runs += runs_by_bin.iter().map(|runs| runs.len()).sum::<usize>();
+        stats.unreduced_entries += runs_by_bin
+            .iter()
+            .flatten()
+            .map(|run| run.len())
+            .sum::<usize>();
+        let de_duped = runs_by_bin
+            .par_iter()
+            .map(|runs| Self::de_dup_spilled_runs(runs))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut total_lamports = 0;
+        let hashes = de_duped
+            .into_iter()
+            .map(|(hashes, sum)| {
+                total_lamports =
+                    Self::checked_cast_for_capitalization(sum + total_lamports as u128);
+                vec![hashes]
+            })
+            .collect();
+        merge_time.stop();
+        stats.sort_time_total_us += merge_time.as_us();
+
+        Ok(Self::hash_de_duped(
+            hashes,
+            total_lamports,
+            stats,
+            is_last_pass,
+            previous_state,
+        ))
+    }
+
+    // input:
+    // vec: PUBKEY_BINS_FOR_CALCULATING_HASHES in pubkey order
+    //   vec: sorted sections from parallelism, in pubkey order
+    //     vec: individual hashes in pubkey order
+    fn hash_de_duped(
+        mut hashes: Vec<Vec<Vec<Hash>>>,
+        mut total_lamports: u64,
+        stats: &mut HashStats,
+        is_last_pass: bool,
+        mut previous_state: PreviousPass,
+    ) -> (Hash, u64, PreviousPass) {
         total_lamports += previous_state.lamports;
 
         if !previous_state.remaining_unhashed.is_empty() {
@@ -894,6 +1267,74 @@ pub mod tests {
         assert_eq!((result.0, result.1), (expected_hash, 118));
     }
 
+    #[test]
+    fn test_accountsdb_rest_of_hash_calculation_spilled() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let keys: Vec<_> = (0..50).map(|_| Pubkey::new_unique()).collect();
+        // several versions of most keys, some of them zero lamport, spread over runs
+        let mut next_run = 0;
+        let mut runs = || {
+            let items: Vec<_> = keys
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| (i + next_run) % 3 != 0)
+                .map(|(i, key)| {
+                    let lamports = if (i + next_run) % 7 == 0 {
+                        ZERO_RAW_LAMPORTS_SENTINEL
+                    } else {
+                        (i * 10 + next_run) as u64
+                    };
+                    let hash = Hash::new(&[(i + next_run) as u8; 32]);
+                    CalculateHashIntermediate::new(next_run as u64, hash, lamports, 5, *key)
+                })
+                .collect();
+            next_run += 1;
+            items
+        };
+        let data: Vec<Vec<_>> = (0..4).map(|_| runs()).collect();
+
+        let expected = AccountsHash::rest_of_hash_calculation(
+            data.iter().map(|items| vec![items.clone()]).collect(),
+            &mut HashStats::default(),
+            true,
+            PreviousPass::default(),
+        );
+        let spilled_runs = data
+            .into_iter()
+            .enumerate()
+            .map(|(i, items)| SpilledRun::write(dir.path().join(i.to_string()), items).unwrap())
+            .collect();
+        let mut stats = HashStats::default();
+        let result = AccountsHash::rest_of_hash_calculation_spilled(
+            vec![spilled_runs],
+            &mut stats,
+            true,
+            PreviousPass::default(),
+        )
+        .unwrap();
+        assert_eq!((result.0, result.1), (expected.0, expected.1));
+        assert_eq!(stats.num_spilled_runs, 4);
+
+        // empty bins and runs are fine
+        let expected = AccountsHash::rest_of_hash_calculation(
+            vec![],
+            &mut HashStats::default(),
+            true,
+            PreviousPass::default(),
+        );
+        let result = AccountsHash::rest_of_hash_calculation_spilled(
+            vec![
+                vec![],
+                vec![SpilledRun::write(dir.path().join("empty"), vec![]).unwrap()],
+            ],
+            &mut HashStats::default(),
+            true,
+            PreviousPass::default(),
+        )
+        .unwrap();
+        assert_eq!((result.0, result.1), (expected.0, expected.1));
+    }
+
     #[test]
     fn test_accountsdb_multi_pass_rest_of_hash_calculation() {
         solana_logger::setup();
@@ -1,6 +1,6 @@
 use crate::{
-    ancestors::Ancestors,
-    contains::Contains,
+    ancestors::{AncestorProvider, Ancestors},
+    contains::{Contains, MaybeContains},
     inline_spl_token_v2_0::{self, SPL_TOKEN_ACCOUNT_MINT_OFFSET, SPL_TOKEN_ACCOUNT_OWNER_OFFSET},
     secondary_index::*,
 };
@@ -16,7 +16,7 @@ use solana_sdk::{
 use std::{
     collections::{
         btree_map::{self, BTreeMap},
-        HashSet,
+        HashMap, HashSet,
     },
     ops::{
         Bound,
@@ -60,13 +60,50 @@ enum ScanTypes<R: RangeBounds<Pubkey>> {
     Indexed(IndexKey),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum IndexKey {
     ProgramId(Pubkey),
     SplTokenMint(Pubkey),
     SplTokenOwner(Pubkey),
 }
 
+impl IndexKey {
+    pub fn key(&self) -> &Pubkey {
+        match self {
+            IndexKey::ProgramId(key) => key,
+            IndexKey::SplTokenMint(key) => key,
+            IndexKey::SplTokenOwner(key) => key,
+        }
+    }
+
+    pub fn account_index(&self) -> AccountIndex {
+        match self {
+            IndexKey::ProgramId(_) => AccountIndex::ProgramId,
+            IndexKey::SplTokenMint(_) => AccountIndex::SplTokenMint,
+            IndexKey::SplTokenOwner(_) => AccountIndex::SplTokenOwner,
+        }
+    }
+
+    /// Whether an account with `account_owner` and `account_data` is listed under this key,
+    /// by the same rules `update_secondary_indexes()` inserts with
+    pub fn matches(&self, account_owner: &Pubkey, account_data: &[u8]) -> bool {
+        let spl_token_key = |offset: usize| {
+            (*account_owner == inline_spl_token_v2_0::id()
+                && account_data.len() == inline_spl_token_v2_0::state::Account::get_packed_len())
+            .then(|| Pubkey::new(&account_data[offset..offset + PUBKEY_BYTES]))
+        };
+        match self {
+            IndexKey::ProgramId(key) => account_owner == key,
+            IndexKey::SplTokenMint(key) => {
+                spl_token_key(SPL_TOKEN_ACCOUNT_MINT_OFFSET).as_ref() == Some(key)
+            }
+            IndexKey::SplTokenOwner(key) => {
+                spl_token_key(SPL_TOKEN_ACCOUNT_OWNER_OFFSET).as_ref() == Some(key)
+            }
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum AccountIndex {
     ProgramId,
@@ -105,6 +142,9 @@ impl AccountSecondaryIndexes {
 pub struct AccountMapEntryInner<T> {
     ref_count: AtomicU64,
     pub slot_list: RwLock<SlotList<T>>,
+    /// Oldest root of the account's current lifetime whose version clean already reclaimed,
+    /// `Slot::MAX` if none was
+    created_at: AtomicU64,
 }
 
 impl<T> AccountMapEntryInner<T> {
@@ -188,6 +228,7 @@ impl<T: 'static + Clone + IsCached> WriteAccountMapEntry<T> {
         Arc::new(AccountMapEntryInner {
             ref_count: AtomicU64::new(ref_count),
             slot_list: RwLock::new(vec![(slot, account_info.clone())]),
+            created_at: AtomicU64::new(Slot::MAX),
         })
     }
 
@@ -407,6 +448,8 @@ pub struct RootsTracker {
     max_root: Slot,
     uncleaned_roots: HashSet<Slot>,
     previous_uncleaned_roots: HashSet<Slot>,
+    /// The highest root a clean has processed
+    max_cleaned_root: Option<Slot>,
 }
 
 impl Default for RootsTracker {
@@ -425,6 +468,7 @@ impl RootsTracker {
             max_root: 0,
             uncleaned_roots: HashSet::new(),
             previous_uncleaned_roots: HashSet::new(),
+            max_cleaned_root: None,
         }
     }
 }
@@ -503,6 +547,82 @@ impl<'a, T: 'static + Clone> Iterator for AccountsIndexIterator<'a, T> {
     }
 }
 
+/// Iterates over the pubkeys in the index in sorted order, `chunk_size` at a time, holding
+/// the index read lock only while each chunk is collected.
+pub struct AccountsIndexKeyIterator<'a, T> {
+    account_maps: &'a RwLock<AccountMap<Pubkey, AccountMapEntry<T>>>,
+    start_bound: Bound<Pubkey>,
+    chunk_size: usize,
+    is_finished: bool,
+}
+
+impl<'a, T> Iterator for AccountsIndexKeyIterator<'a, T> {
+    type Item = Vec<Pubkey>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.is_finished {
+            return None;
+        }
+
+        let chunk: Vec<Pubkey> = self
+            .account_maps
+            .read()
+            .unwrap()
+            .range((self.start_bound, Unbounded))
+            .map(|(pubkey, _account_map_entry)| *pubkey)
+            .take(self.chunk_size)
+            .collect();
+
+        if chunk.len() < self.chunk_size {
+            self.is_finished = true;
+        }
+        if chunk.is_empty() {
+            return None;
+        }
+
+        self.start_bound = Excluded(*chunk.last().unwrap());
+        Some(chunk)
+    }
+}
+
+/// A scan walking the index in pubkey order, registered so clean can tell which keys it's
+/// done with, see `AccountsIndex::sorted_scans_behind()`
+#[derive(Debug)]
+struct SortedScan {
+    root: Slot,
+    range: (Bound<Pubkey>, Bound<Pubkey>),
+    /// The last pubkey the scan is done with, None until it's done with any
+    passed: RwLock<Option<Pubkey>>,
+}
+
+/// `range` as bounds that own their pubkeys, so they can be kept or handed out more than once
+pub(crate) fn owned_range_bounds<R>(range: &R) -> (Bound<Pubkey>, Bound<Pubkey>)
+where
+    R: RangeBounds<Pubkey>,
+{
+    let owned = |bound: Bound<&Pubkey>| match bound {
+        Included(pubkey) => Included(*pubkey),
+        Excluded(pubkey) => Excluded(*pubkey),
+        Unbounded => Unbounded,
+    };
+    (owned(range.start_bound()), owned(range.end_bound()))
+}
+
+/// Where an ongoing sorted scan was at, as of `AccountsIndex::sorted_scans_behind()`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SortedScanPosition {
+    range: (Bound<Pubkey>, Bound<Pubkey>),
+    passed: Option<Pubkey>,
+}
+
+impl SortedScanPosition {
+    /// Whether the scan is still to reach `pubkey`, and so needs the version current at its
+    /// root kept
+    pub fn needs(&self, pubkey: &Pubkey) -> bool {
+        RangeBounds::contains(&self.range, pubkey)
+            && self.passed.map(|passed| *pubkey > passed).unwrap_or(true)
+    }
+}
+
 pub trait ZeroLamport {
     fn is_zero_lamport(&self) -> bool;
 }
@@ -519,7 +639,14 @@ pub struct AccountsIndex<T> {
     spl_token_owner_index: SecondaryIndex<RwLockSecondaryIndexEntry>,
     roots_tracker: RwLock<RootsTracker>,
     ongoing_scan_roots: RwLock<BTreeMap<Slot, u64>>,
+    /// Unindexed scans, which walk the index in pubkey order. Clean keeps only the keys they're
+    /// still to reach as of their roots, rather than everything, see `sorted_scans_behind()`.
+    ongoing_sorted_scans: RwLock<Vec<Arc<SortedScan>>>,
     zero_lamport_pubkeys: DashSet<Pubkey>,
+    /// Number of roots added
+    generation: AtomicU64,
+    /// `generation()` as of the last insert into each slot that isn't a root
+    unrooted_slots: RwLock<HashMap<Slot, u64>>,
 }
 
 impl<T> Default for AccountsIndex<T> {
@@ -537,7 +664,10 @@ impl<T> Default for AccountsIndex<T> {
             ),
             roots_tracker: RwLock::<RootsTracker>::default(),
             ongoing_scan_roots: RwLock::<BTreeMap<Slot, u64>>::default(),
+            ongoing_sorted_scans: RwLock::default(),
             zero_lamport_pubkeys: DashSet::<Pubkey>::default(),
+            generation: AtomicU64::default(),
+            unrooted_slots: RwLock::default(),
         }
     }
 }
@@ -550,33 +680,63 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
         AccountsIndexIterator::new(&self.account_maps, range)
     }
 
+    /// Iterate over all pubkeys in sorted order without cloning the whole key set up front
+    pub fn iter_keys_chunked(&self, chunk_size: usize) -> AccountsIndexKeyIterator<'_, T> {
+        assert!(chunk_size > 0);
+        AccountsIndexKeyIterator {
+            account_maps: &self.account_maps,
+            start_bound: Unbounded,
+            chunk_size,
+            is_finished: false,
+        }
+    }
+
     fn do_checked_scan_accounts<F, R>(
         &self,
         metric_name: &'static str,
-        ancestors: &Ancestors,
+        ancestors: &dyn AncestorProvider,
         func: F,
         scan_type: ScanTypes<R>,
     ) where
         F: FnMut(&Pubkey, (&T, Slot)),
         R: RangeBounds<Pubkey>,
     {
-        let max_root = {
-            let mut w_ongoing_scan_roots = self
-                // This lock is also grabbed by clean_accounts(), so clean
-                // has at most cleaned up to the current `max_root` (since
-                // clean only happens *after* BankForks::set_root() which sets
-                // the `max_root`)
-                .ongoing_scan_roots
-                .write()
-                .unwrap();
-            // `max_root()` grabs a lock while
-            // the `ongoing_scan_roots` lock is held,
-            // make sure inverse doesn't happen to avoid
-            // deadlock
-            let max_root = self.max_root();
-            *w_ongoing_scan_roots.entry(max_root).or_default() += 1;
-
-            max_root
+        let sorted_scan_range = match &scan_type {
+            ScanTypes::Unindexed(Some(range)) => Some(owned_range_bounds(range)),
+            ScanTypes::Unindexed(None) => Some((Unbounded, Unbounded)),
+            ScanTypes::Indexed(_) => None,
+        };
+        let (max_root, sorted_scan) = match sorted_scan_range {
+            Some(range) => {
+                // read with the lock held, for the same reason as `ongoing_scan_roots` below
+                let mut w_ongoing_sorted_scans = self.ongoing_sorted_scans.write().unwrap();
+                let max_root = self.max_root();
+                let sorted_scan = Arc::new(SortedScan {
+                    root: max_root,
+                    range,
+                    passed: RwLock::default(),
+                });
+                w_ongoing_sorted_scans.push(sorted_scan.clone());
+                (max_root, Some(sorted_scan))
+            }
+            None => {
+                let mut w_ongoing_scan_roots = self
+                    // This lock is also grabbed by clean_accounts(), so clean
+                    // has at most cleaned up to the current `max_root` (since
+                    // clean only happens *after* BankForks::set_root() which sets
+                    // the `max_root`)
+                    .ongoing_scan_roots
+                    .write()
+                    .unwrap();
+                // `max_root()` grabs a lock while
+                // the `ongoing_scan_roots` lock is held,
+                // make sure inverse doesn't happen to avoid
+                // deadlock
+                let max_root = self.max_root();
+                *w_ongoing_scan_roots.entry(max_root).or_default() += 1;
+
+                (max_root, None)
+            }
         };
 
         // First we show that for any bank `B` that is a descendant of
@@ -697,7 +857,14 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
         match scan_type {
             ScanTypes::Unindexed(range) => {
                 // Pass "" not to log metrics, so RPC doesn't get spammy
-                self.do_scan_accounts(metric_name, ancestors, func, range, Some(max_root));
+                self.do_scan_accounts(
+                    metric_name,
+                    ancestors,
+                    func,
+                    range,
+                    Some(max_root),
+                    sorted_scan.as_deref(),
+                );
             }
             ScanTypes::Indexed(IndexKey::ProgramId(program_id)) => {
                 self.do_scan_secondary_index(
@@ -728,39 +895,97 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
             }
         }
 
-        {
-            let mut ongoing_scan_roots = self.ongoing_scan_roots.write().unwrap();
-            let count = ongoing_scan_roots.get_mut(&max_root).unwrap();
-            *count -= 1;
-            if *count == 0 {
-                ongoing_scan_roots.remove(&max_root);
+        match sorted_scan {
+            Some(sorted_scan) => self
+                .ongoing_sorted_scans
+                .write()
+                .unwrap()
+                .retain(|scan| !Arc::ptr_eq(scan, &sorted_scan)),
+            None => self.release_scan_root(max_root),
+        }
+    }
+
+    /// Where the ongoing sorted scans at roots older than `max_clean_root` are at. A clean up to
+    /// `max_clean_root` must leave alone the keys any of them `needs()`, whose versions current
+    /// at their roots they're still to read. Scans only move forward, so a key a scan was done
+    /// with stays so.
+    pub(crate) fn sorted_scans_behind(&self, max_clean_root: Slot) -> Vec<SortedScanPosition> {
+        self.ongoing_sorted_scans
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|scan| scan.root < max_clean_root)
+            .map(|scan| SortedScanPosition {
+                range: scan.range,
+                passed: *scan.passed.read().unwrap(),
+            })
+            .collect()
+    }
+
+    /// Keeps clean from going past `root` until `release_scan_root()`, for reads of the state
+    /// at `root` done outside of the index
+    pub(crate) fn hold_scan_root(&self, root: Slot) {
+        *self
+            .ongoing_scan_roots
+            .write()
+            .unwrap()
+            .entry(root)
+            .or_default() += 1;
+    }
+
+    pub(crate) fn release_scan_root(&self, root: Slot) {
+        let mut ongoing_scan_roots = self.ongoing_scan_roots.write().unwrap();
+        let count = ongoing_scan_roots.get_mut(&root).unwrap();
+        *count -= 1;
+        if *count == 0 {
+            ongoing_scan_roots.remove(&root);
+        }
+    }
+
+    /// The newest version rooted at or before `max_root` of each pubkey in `range`, in pubkey
+    /// order. Nothing is loaded, and the versions may be reclaimed once this returns unless
+    /// `max_root` is held with `hold_scan_root()`.
+    pub(crate) fn rooted_range_refs<R>(&self, range: R, max_root: Slot) -> Vec<(Pubkey, Slot, T)>
+    where
+        R: RangeBounds<Pubkey>,
+    {
+        let mut refs = vec![];
+        for pubkey_list in self.iter(Some(range)) {
+            for (pubkey, list) in pubkey_list {
+                let list_r = &list.slot_list.read().unwrap();
+                if let Some(index) = self.latest_slot(None, list_r, Some(max_root)) {
+                    refs.push((pubkey, list_r[index].0, list_r[index].1.clone()));
+                }
             }
         }
+        refs
     }
 
     fn do_unchecked_scan_accounts<F, R>(
         &self,
         metric_name: &'static str,
-        ancestors: &Ancestors,
+        ancestors: &dyn AncestorProvider,
         func: F,
         range: Option<R>,
     ) where
         F: FnMut(&Pubkey, (&T, Slot)),
         R: RangeBounds<Pubkey>,
     {
-        self.do_scan_accounts(metric_name, ancestors, func, range, None);
+        self.do_scan_accounts(metric_name, ancestors, func, range, None, None);
     }
 
     // Scan accounts and return latest version of each account that is either:
     // 1) rooted or
     // 2) present in ancestors
+    // `sorted_scan` is told about every chunk of keys the scan is done with.
     fn do_scan_accounts<F, R>(
         &self,
         metric_name: &'static str,
-        ancestors: &Ancestors,
+        ancestors: &dyn AncestorProvider,
         mut func: F,
         range: Option<R>,
         max_root: Option<Slot>,
+        sorted_scan: Option<&SortedScan>,
     ) where
         F: FnMut(&Pubkey, (&T, Slot)),
         R: RangeBounds<Pubkey>,
@@ -777,6 +1002,7 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
         for pubkey_list in self.iter(range) {
             iterator_timer.stop();
             iterator_elapsed += iterator_timer.as_us();
+            let last_pubkey = pubkey_list.last().map(|(pubkey, _)| *pubkey);
             for (pubkey, list) in pubkey_list {
                 num_keys_iterated += 1;
                 let mut read_lock_timer = Measure::start("read_lock");
@@ -793,6 +1019,9 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
                     load_account_elapsed += load_account_timer.as_us();
                 }
             }
+            if let Some((sorted_scan, last_pubkey)) = sorted_scan.zip(last_pubkey) {
+                *sorted_scan.passed.write().unwrap() = Some(last_pubkey);
+            }
             iterator_timer = Measure::start("iterator_elapsed");
         }
 
@@ -815,7 +1044,7 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
         SecondaryIndexEntryType: SecondaryIndexEntry + Default + Sync + Send,
     >(
         &self,
-        ancestors: &Ancestors,
+        ancestors: &dyn AncestorProvider,
         mut func: F,
         index: &SecondaryIndex<SecondaryIndexEntryType>,
         index_key: &Pubkey,
@@ -935,7 +1164,7 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
     }
 
     /// call func with every pubkey and index visible from a given set of ancestors
-    pub(crate) fn scan_accounts<F>(&self, ancestors: &Ancestors, func: F)
+    pub(crate) fn scan_accounts<F>(&self, ancestors: &dyn AncestorProvider, func: F)
     where
         F: FnMut(&Pubkey, (&T, Slot)),
     {
@@ -951,7 +1180,7 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
     pub(crate) fn unchecked_scan_accounts<F>(
         &self,
         metric_name: &'static str,
-        ancestors: &Ancestors,
+        ancestors: &dyn AncestorProvider,
         func: F,
     ) where
         F: FnMut(&Pubkey, (&T, Slot)),
@@ -963,7 +1192,7 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
     pub(crate) fn range_scan_accounts<F, R>(
         &self,
         metric_name: &'static str,
-        ancestors: &Ancestors,
+        ancestors: &dyn AncestorProvider,
         range: R,
         func: F,
     ) where
@@ -975,8 +1204,12 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
     }
 
     /// call func with every pubkey and index visible from a given set of ancestors
-    pub(crate) fn index_scan_accounts<F>(&self, ancestors: &Ancestors, index_key: IndexKey, func: F)
-    where
+    pub(crate) fn index_scan_accounts<F>(
+        &self,
+        ancestors: &dyn AncestorProvider,
+        index_key: IndexKey,
+        func: F,
+    ) where
         F: FnMut(&Pubkey, (&T, Slot)),
     {
         // Pass "" not to log metrics, so RPC doesn't get spammy
@@ -988,6 +1221,18 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
         );
     }
 
+    /// Collect the pubkeys in the index that may be members of `filter`.
+    /// Doesn't hold the index lock across the whole scan.
+    pub(crate) fn pubkeys_maybe_in<F>(&self, filter: &F) -> Vec<Pubkey>
+    where
+        F: MaybeContains<Pubkey>,
+    {
+        self.iter_keys_chunked(ITER_BATCH_SIZE)
+            .flatten()
+            .filter(|pubkey| filter.maybe_contains(pubkey))
+            .collect()
+    }
+
     pub fn get_rooted_entries(&self, slice: SlotSlice<T>, max: Option<Slot>) -> SlotList<T> {
         let max = max.unwrap_or(Slot::MAX);
         let lock = &self.roots_tracker.read().unwrap().roots;
@@ -1020,7 +1265,8 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
         C: Contains<'a, Slot>,
     {
         let mut write_account_map_entry = self.get_account_write_entry(pubkey).unwrap();
-        write_account_map_entry.slot_list_mut(|slot_list| {
+        let reclaims_start = reclaims.len();
+        let is_empty = write_account_map_entry.slot_list_mut(|slot_list| {
             slot_list.retain(|(slot, item)| {
                 let should_purge = slots_to_purge.contains(&slot);
                 if should_purge {
@@ -1031,10 +1277,153 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
                 }
             });
             slot_list.is_empty()
-        })
+        });
+        self.retire_versions(
+            write_account_map_entry.borrow_owned_entry_contents(),
+            &reclaims[reclaims_start..],
+        );
+        is_empty
+    }
+
+    /// Carries the creation slot of `entry` over its rooted versions in `removed` being
+    /// reclaimed. A reclaimed zero-lamport version ends the lifetime it belonged to, so the
+    /// account was created again by the version after it.
+    fn retire_versions(&self, entry: &AccountMapEntryInner<T>, removed: SlotSlice<T>) {
+        let mut removed: Vec<_> = {
+            let roots_tracker = self.roots_tracker.read().unwrap();
+            removed
+                .iter()
+                .filter(|(slot, _)| roots_tracker.roots.contains(slot))
+                .collect()
+        };
+        removed.sort_unstable_by_key(|(slot, _)| *slot);
+        match removed.iter().rposition(|(_, info)| info.is_zero_lamport()) {
+            Some(last_zero_lamport) => {
+                let created_at = removed
+                    .get(last_zero_lamport + 1)
+                    .map_or(Slot::MAX, |(slot, _)| *slot);
+                entry.created_at.store(created_at, Ordering::Relaxed);
+            }
+            None => {
+                if let Some((oldest, _)) = removed.first() {
+                    entry.created_at.fetch_min(*oldest, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    /// The root `pubkey` was created in: that of its oldest version after the last rooted
+    /// version with zero lamports, including versions clean already reclaimed. `None` unless
+    /// the account exists as of the latest root.
+    pub fn account_created_at(&self, pubkey: &Pubkey) -> Option<Slot> {
+        let entry = self.get_account_read_entry(pubkey)?;
+        let mut rooted: Vec<_> = {
+            let roots_tracker = self.roots_tracker.read().unwrap();
+            entry
+                .slot_list()
+                .iter()
+                .filter(|(slot, _)| roots_tracker.roots.contains(slot))
+                .collect()
+        };
+        rooted.sort_unstable_by_key(|(slot, _)| *slot);
+        let mut created_at = entry
+            .borrow_owned_entry_contents()
+            .created_at
+            .load(Ordering::Relaxed);
+        for (slot, info) in &rooted {
+            if info.is_zero_lamport() {
+                created_at = Slot::MAX;
+            } else if created_at == Slot::MAX {
+                created_at = *slot;
+            }
+        }
+        (created_at != Slot::MAX).then(|| created_at)
+    }
+
+    /// The creation slots, up to `max_slot`, kept for accounts whose versions they were taken
+    /// from are reclaimed already, so they can be restored along with the storages
+    pub fn created_at_slots(&self, max_slot: Slot) -> Vec<(Pubkey, Slot)> {
+        self.account_maps
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|(pubkey, entry)| {
+                let created_at = entry.created_at.load(Ordering::Relaxed);
+                (created_at != Slot::MAX && created_at <= max_slot).then(|| (*pubkey, created_at))
+            })
+            .collect()
+    }
+
+    /// Restores creation slots `created_at_slots()` returned, once the index was generated
+    pub fn restore_created_at_slots(&self, created_at_slots: &[(Pubkey, Slot)]) {
+        let account_maps = self.account_maps.read().unwrap();
+        for (pubkey, created_at) in created_at_slots {
+            if let Some(entry) = account_maps.get(pubkey) {
+                entry.created_at.fetch_min(*created_at, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Like `purge_exact`, but purges the entries matching `should_purge` and tolerates
+    /// pubkeys missing from the index. Returns whether the slot list is now empty.
+    pub(crate) fn purge_matching<F>(&self, pubkey: &Pubkey, should_purge: F) -> bool
+    where
+        F: Fn(Slot, &T) -> bool,
+    {
+        match self.get_account_write_entry(pubkey) {
+            Some(mut write_account_map_entry) => {
+                write_account_map_entry.slot_list_mut(|slot_list| {
+                    slot_list.retain(|(slot, item)| !should_purge(*slot, item));
+                    slot_list.is_empty()
+                })
+            }
+            None => false,
+        }
+    }
+
+    /// Replaces the entry of `slot` in the slot list of `pubkey` with what `patch` returns
+    /// for it, if anything. The ref count is left alone. Returns whether the entry was
+    /// replaced.
+    pub(crate) fn patch_slot_entry<F>(&self, pubkey: &Pubkey, slot: Slot, patch: F) -> bool
+    where
+        F: FnOnce(&T) -> Option<T>,
+    {
+        match self.get_account_write_entry(pubkey) {
+            Some(mut write_account_map_entry) => {
+                write_account_map_entry.slot_list_mut(|slot_list| {
+                    let item = slot_list
+                        .iter_mut()
+                        .find(|(entry_slot, _)| *entry_slot == slot);
+                    match item.and_then(|(_, item)| Some((patch(item)?, item))) {
+                        Some((patched, item)) => {
+                            *item = patched;
+                            true
+                        }
+                        None => false,
+                    }
+                })
+            }
+            None => false,
+        }
     }
 
     pub fn min_ongoing_scan_root(&self) -> Option<Slot> {
+        let min_sorted_scan_root = self
+            .ongoing_sorted_scans
+            .read()
+            .unwrap()
+            .iter()
+            .map(|scan| scan.root)
+            .min();
+        self.min_ongoing_unsorted_scan_root()
+            .into_iter()
+            .chain(min_sorted_scan_root)
+            .min()
+    }
+
+    /// Like `min_ongoing_scan_root()`, leaving out the sorted scans, which only hold clean
+    /// back for the keys they're still to reach, see `sorted_scans_behind()`
+    pub fn min_ongoing_unsorted_scan_root(&self) -> Option<Slot> {
         self.ongoing_scan_roots
             .read()
             .unwrap()
@@ -1047,7 +1436,7 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
     // in `L`, where the slot `S` is an ancestor or root, and if `S` is a root, then `S <= max_root`
     fn latest_slot(
         &self,
-        ancestors: Option<&Ancestors>,
+        ancestors: Option<&dyn AncestorProvider>,
         slice: SlotSlice<T>,
         max_root: Option<Slot>,
     ) -> Option<usize> {
@@ -1089,7 +1478,7 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
     pub(crate) fn get(
         &self,
         pubkey: &Pubkey,
-        ancestors: Option<&Ancestors>,
+        ancestors: Option<&dyn AncestorProvider>,
         max_root: Option<Slot>,
     ) -> AccountIndexGetResult<'_, T> {
         let read_lock = self.account_maps.read().unwrap();
@@ -1186,6 +1575,46 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
         }
     }
 
+    /// Removes `pubkey`, just written with zero lamports at `slot`, from the spl token
+    /// secondary indexes instead of leaving it there until clean purges its index entry.
+    /// That's only done when no scan can find a live version of it anymore: every version in
+    /// the index has zero lamports, or `slot` is a root and every live version is older.
+    /// The entry is held locked meanwhile, so a racing store of a live version re-inserts it
+    /// after the removal. Returns whether `pubkey` was removed.
+    pub(crate) fn remove_closed_token_account_from_secondary_indexes(
+        &self,
+        pubkey: &Pubkey,
+        slot: Slot,
+        account_indexes: &AccountSecondaryIndexes,
+    ) -> bool {
+        let remove_owner = account_indexes.contains(&AccountIndex::SplTokenOwner);
+        let remove_mint = account_indexes.contains(&AccountIndex::SplTokenMint);
+        if !remove_owner && !remove_mint {
+            return false;
+        }
+        let locked_entry = match self.get_account_read_entry(pubkey) {
+            Some(locked_entry) => locked_entry,
+            None => return false,
+        };
+        let slot_list = locked_entry.slot_list();
+        let all_dead = slot_list.iter().all(|(_slot, info)| info.is_zero_lamport());
+        let is_closed = all_dead
+            || (self.is_root(slot)
+                && slot_list
+                    .iter()
+                    .all(|(entry_slot, info)| *entry_slot < slot || info.is_zero_lamport()));
+        if !is_closed {
+            return false;
+        }
+        if remove_owner {
+            self.spl_token_owner_index.remove_by_inner_key(pubkey);
+        }
+        if remove_mint {
+            self.spl_token_mint_index.remove_by_inner_key(pubkey);
+        }
+        true
+    }
+
     fn get_account_maps_write_lock(&self) -> AccountMapsWriteLock<T> {
         self.account_maps.write().unwrap()
     }
@@ -1194,48 +1623,72 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
         self.account_maps.read().unwrap()
     }
 
+    /// Same as `upsert()` for every item in turn, minus the secondary indexes, which are
+    /// left to the caller. The account map read lock is taken once for the whole batch to
+    /// update the existing entries, and the write lock once more to insert the missing ones,
+    /// rather than once or twice per item. Returns whether each pubkey was newly inserted.
+    pub fn upsert_batch(
+        &self,
+        slot: Slot,
+        items: Vec<(&Pubkey, T)>,
+        reclaims: &mut SlotList<T>,
+    ) -> Vec<bool> {
+        self.track_unrooted_insert(slot);
+        let mut newly_inserted = vec![false; items.len()];
+        let mut missing_items = vec![];
+        {
+            // held while the existing entries are updated, the same as `upsert()` does, so
+            // a clean can't remove an entry from the map in between and orphan the update
+            let r_account_maps = self.get_account_maps_read_lock();
+            for (i, (pubkey, account_info)) in items.into_iter().enumerate() {
+                if account_info.is_zero_lamport() {
+                    self.zero_lamport_pubkeys.insert(*pubkey);
+                }
+                match r_account_maps.get(pubkey) {
+                    Some(existing_entry) => {
+                        WriteAccountMapEntry::from_account_map_entry(existing_entry.clone())
+                            .update(slot, account_info, reclaims);
+                    }
+                    None => {
+                        // this value is equivalent to what update() below would have created if we inserted a new item
+                        let new_entry =
+                            WriteAccountMapEntry::new_entry_after_update(slot, &account_info);
+                        missing_items.push((i, pubkey, account_info, new_entry));
+                    }
+                }
+            }
+        }
+        if missing_items.is_empty() {
+            return newly_inserted;
+        }
+
+        // created all data prior to obtaining the lock
+        let mut w_account_maps = self.get_account_maps_write_lock();
+        for (i, pubkey, account_info, new_entry) in missing_items {
+            match self.insert_new_entry_if_missing_with_lock(pubkey, &mut w_account_maps, new_entry)
+            {
+                // inserted meanwhile, or earlier in the batch
+                Some(mut w_account_entry) => w_account_entry.update(slot, account_info, reclaims),
+                None => newly_inserted[i] = true,
+            }
+        }
+        newly_inserted
+    }
+
     // Same functionally to upsert, but:
-    // 1. operates on a batch of items
-    // 2. holds the write lock for the duration of adding the items
-    // Can save time when inserting lots of new keys.
-    // But, does NOT update secondary index
+    // 1. operates on a batch of items, see `upsert_batch()`
+    // 2. does NOT update secondary index
+    // 3. returns whether each pubkey was already in the index
     // This is designed to be called at startup time.
-    #[allow(clippy::needless_collect)]
     pub(crate) fn insert_new_if_missing_into_primary_index(
         &self,
         slot: Slot,
         items: Vec<(&Pubkey, T)>,
     ) -> Vec<bool> {
-        let potentially_new_items = items
-            .iter()
-            .map(|(_pubkey, account_info)| {
-                // this value is equivalent to what update() below would have created if we inserted a new item
-                WriteAccountMapEntry::new_entry_after_update(slot, account_info)
-            })
-            .collect::<Vec<_>>(); // collect here so we have created all data prior to obtaining lock
-
         let mut _reclaims = SlotList::new();
-
-        let mut w_account_maps = self.get_account_maps_write_lock();
-        items
+        self.upsert_batch(slot, items, &mut _reclaims)
             .into_iter()
-            .zip(potentially_new_items.into_iter())
-            .map(|((pubkey, account_info), new_item)| {
-                let account_entry = self.insert_new_entry_if_missing_with_lock(
-                    pubkey,
-                    &mut w_account_maps,
-                    new_item,
-                );
-                if account_info.is_zero_lamport() {
-                    self.zero_lamport_pubkeys.insert(*pubkey);
-                }
-                if let Some(mut w_account_entry) = account_entry {
-                    w_account_entry.update(slot, account_info, &mut _reclaims);
-                    true
-                } else {
-                    false
-                }
-            })
+            .map(|newly_inserted| !newly_inserted)
             .collect()
     }
 
@@ -1252,6 +1705,7 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
         account_info: T,
         reclaims: &mut SlotList<T>,
     ) -> bool {
+        self.track_unrooted_insert(slot);
         let is_newly_inserted = {
             let w_account_entry =
                 self.get_account_write_entry_else_create(pubkey, slot, &account_info);
@@ -1344,6 +1798,20 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
         });
     }
 
+    /// The entries of `pubkey` that `clean_rooted_entries()` would reclaim, left in place
+    pub(crate) fn older_root_entries(
+        &self,
+        pubkey: &Pubkey,
+        max_clean_root: Option<Slot>,
+    ) -> SlotList<T> {
+        let mut reclaims = Vec::new();
+        if let Some(locked_entry) = self.get_account_read_entry(pubkey) {
+            let mut slot_list = locked_entry.slot_list().clone();
+            self.purge_older_root_entries(&mut slot_list, &mut reclaims, max_clean_root);
+        }
+        reclaims
+    }
+
     pub fn clean_rooted_entries(
         &self,
         pubkey: &Pubkey,
@@ -1352,10 +1820,15 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
     ) {
         let mut is_slot_list_empty = false;
         if let Some(mut locked_entry) = self.get_account_write_entry(pubkey) {
+            let reclaims_start = reclaims.len();
             locked_entry.slot_list_mut(|slot_list| {
                 self.purge_older_root_entries(slot_list, reclaims, max_clean_root);
                 is_slot_list_empty = slot_list.is_empty();
             });
+            self.retire_versions(
+                locked_entry.borrow_owned_entry_contents(),
+                &reclaims[reclaims_start..],
+            );
         }
 
         // If the slot list is empty, remove the pubkey from `account_maps`.  Make sure to grab the
@@ -1410,7 +1883,48 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
         self.roots_tracker.read().unwrap().roots.contains(&slot)
     }
 
+    /// The highest root a clean has processed. Versions superseded by an update in a root above
+    /// it are still in the index.
+    pub fn max_cleaned_root(&self) -> Option<Slot> {
+        self.roots_tracker.read().unwrap().max_cleaned_root
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+
+    // Stamps `slot` with the current generation unless it's a root, see `expired_fork_slots()`
+    fn track_unrooted_insert(&self, slot: Slot) {
+        let generation = self.generation();
+        if self.unrooted_slots.read().unwrap().get(&slot) == Some(&generation) || self.is_root(slot)
+        {
+            return;
+        }
+        self.unrooted_slots
+            .write()
+            .unwrap()
+            .insert(slot, generation);
+    }
+
+    /// The slots below the max root that aren't roots and weren't written to for `ttl`
+    /// generations. Such slots belong to abandoned forks and can never become roots.
+    pub fn expired_fork_slots(&self, ttl: u64) -> HashSet<Slot> {
+        let generation = self.generation();
+        let mut unrooted_slots = self.unrooted_slots.write().unwrap();
+        let roots_tracker = self.roots_tracker.read().unwrap();
+        // slots rooted while they were being written to
+        unrooted_slots.retain(|slot, _generation| !roots_tracker.roots.contains(slot));
+        unrooted_slots
+            .iter()
+            .filter(|(slot, last_insert)| {
+                **slot < roots_tracker.max_root && last_insert.saturating_add(ttl) <= generation
+            })
+            .map(|(slot, _last_insert)| *slot)
+            .collect()
+    }
+
     pub fn add_root(&self, slot: Slot, caching_enabled: bool) {
+        self.generation.fetch_add(1, Ordering::Relaxed);
         let mut w_roots_tracker = self.roots_tracker.write().unwrap();
         w_roots_tracker.roots.insert(slot);
         // we delay cleaning until flushing!
@@ -1420,6 +1934,8 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
         // `AccountsDb::flush_accounts_cache()` relies on roots being added in order
         assert!(slot >= w_roots_tracker.max_root);
         w_roots_tracker.max_root = slot;
+        drop(w_roots_tracker);
+        self.unrooted_slots.write().unwrap().remove(&slot);
     }
 
     pub fn add_uncleaned_roots<I>(&self, roots: I)
@@ -1437,6 +1953,7 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
     /// Remove the slot when the storage for the slot is freed
     /// Accounts no longer reference this slot.
     pub fn clean_dead_slot(&self, slot: Slot) -> Option<AccountsIndexRootsStats> {
+        self.unrooted_slots.write().unwrap().remove(&slot);
         let (roots_len, uncleaned_roots_len, previous_uncleaned_roots_len, roots_range) = {
             let mut w_roots_tracker = self.roots_tracker.write().unwrap();
             let removed_from_unclean_roots = w_roots_tracker.uncleaned_roots.remove(&slot);
@@ -1490,6 +2007,11 @@ impl<T: 'static + Clone + IsCached + ZeroLamport> AccountsIndex<T> {
             // Only keep the slots that have yet to be cleaned
             !is_cleaned
         });
+        w_roots_tracker.max_cleaned_root = cleaned_roots
+            .iter()
+            .copied()
+            .chain(w_roots_tracker.max_cleaned_root)
+            .max();
         std::mem::replace(&mut w_roots_tracker.previous_uncleaned_roots, cleaned_roots)
     }
 
@@ -2263,6 +2785,42 @@ pub mod tests {
         assert_eq!(num, 0);
     }
 
+    #[test]
+    fn test_expired_fork_slots() {
+        let index = AccountsIndex::<bool>::default();
+        let upsert = |slot| {
+            index.upsert(
+                slot,
+                &solana_sdk::pubkey::new_rand(),
+                &Pubkey::default(),
+                &[],
+                &AccountSecondaryIndexes::default(),
+                true,
+                &mut Vec::new(),
+            );
+        };
+        upsert(1);
+        upsert(2);
+        upsert(3);
+        index.add_root(2, false);
+        // slot 3 is above the max root, so it may still become one
+        assert!(index.expired_fork_slots(2).is_empty());
+        assert_eq!(index.expired_fork_slots(1), vec![1].into_iter().collect());
+
+        // writing to a slot restarts its ttl
+        upsert(1);
+        assert!(index.expired_fork_slots(1).is_empty());
+        index.add_root(4, false);
+        assert_eq!(
+            index.expired_fork_slots(1),
+            vec![1, 3].into_iter().collect()
+        );
+
+        // purged slots are no longer tracked
+        index.clean_dead_slot(1);
+        assert_eq!(index.expired_fork_slots(1), vec![3].into_iter().collect());
+    }
+
     type AccountInfoTest = f64;
 
     impl IsCached for AccountInfoTest {
@@ -2371,6 +2929,38 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_upsert_batch() {
+        let key0 = Keypair::new().pubkey();
+        let key1 = Keypair::new().pubkey();
+        let index = AccountsIndex::<u64>::default();
+        let mut reclaims = vec![];
+        index.upsert(
+            0,
+            &key0,
+            &Pubkey::default(),
+            &[],
+            &AccountSecondaryIndexes::default(),
+            1,
+            &mut reclaims,
+        );
+
+        // key1 is stored twice in the batch, like an account stored twice in a slot
+        let newly_inserted =
+            index.upsert_batch(1, vec![(&key0, 2), (&key1, 3), (&key1, 4)], &mut reclaims);
+        assert_eq!(newly_inserted, vec![false, true, false]);
+        assert_eq!(reclaims, vec![(1, 3)]);
+        let entry = index.get_account_read_entry(&key0).unwrap();
+        assert_eq!(entry.slot_list().to_vec(), vec![(0, 1), (1, 2)]);
+        assert_eq!(entry.ref_count().load(Ordering::Relaxed), 2);
+        let entry = index.get_account_read_entry(&key1).unwrap();
+        assert_eq!(entry.slot_list().to_vec(), vec![(1, 4)]);
+        assert_eq!(entry.ref_count().load(Ordering::Relaxed), 1);
+
+        // an empty batch does nothing
+        assert!(index.upsert_batch(2, vec![], &mut reclaims).is_empty());
+    }
+
     fn test_new_entry_code_paths_helper<
         T: 'static + Clone + IsCached + ZeroLamport + std::cmp::PartialEq + std::fmt::Debug,
     >(
@@ -2528,7 +3118,7 @@ pub mod tests {
         );
         assert!(gc.is_empty());
 
-        let ancestors = vec![(1, 1)].into_iter().collect();
+        let ancestors: Ancestors = vec![(1, 1)].into_iter().collect();
         assert!(index.get(&key.pubkey(), Some(&ancestors), None).is_none());
 
         let mut num = 0;
@@ -2552,7 +3142,7 @@ pub mod tests {
         );
         assert!(gc.is_empty());
 
-        let ancestors = vec![(0, 0)].into_iter().collect();
+        let ancestors: Ancestors = vec![(0, 0)].into_iter().collect();
         let (list, idx) = index.get(&key.pubkey(), Some(&ancestors), None).unwrap();
         assert_eq!(list.slot_list()[idx], (0, true));
 
@@ -2662,6 +3252,25 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn test_iter_keys_chunked() {
+        let (index, mut pubkeys) = setup_accounts_index_keys(2 * ITER_BATCH_SIZE + 1);
+        pubkeys.sort();
+
+        let chunks: Vec<Vec<Pubkey>> = index.iter_keys_chunked(ITER_BATCH_SIZE).collect();
+        assert_eq!(
+            chunks.iter().map(|chunk| chunk.len()).collect::<Vec<_>>(),
+            vec![ITER_BATCH_SIZE, ITER_BATCH_SIZE, 1]
+        );
+        assert_eq!(chunks.concat(), pubkeys);
+
+        let chunks: Vec<Vec<Pubkey>> = index.iter_keys_chunked(pubkeys.len()).collect();
+        assert_eq!(chunks, vec![pubkeys]);
+
+        let index = AccountsIndex::<bool>::default();
+        assert_eq!(index.iter_keys_chunked(1).next(), None);
+    }
+
     #[test]
     fn test_range_scan_accounts() {
         let (index, mut pubkeys) = setup_accounts_index_keys(3 * ITER_BATCH_SIZE);
@@ -2858,7 +3467,7 @@ pub mod tests {
     fn test_update_last_wins() {
         let key = Keypair::new();
         let index = AccountsIndex::<bool>::default();
-        let ancestors = vec![(0, 0)].into_iter().collect();
+        let ancestors: Ancestors = vec![(0, 0)].into_iter().collect();
         let mut gc = Vec::new();
         index.upsert(
             0,
@@ -2894,7 +3503,7 @@ pub mod tests {
         solana_logger::setup();
         let key = Keypair::new();
         let index = AccountsIndex::<bool>::default();
-        let ancestors = vec![(0, 0)].into_iter().collect();
+        let ancestors: Ancestors = vec![(0, 0)].into_iter().collect();
         let mut gc = Vec::new();
         index.upsert(
             0,
@@ -2918,7 +3527,7 @@ pub mod tests {
         assert!(gc.is_empty());
         let (list, idx) = index.get(&key.pubkey(), Some(&ancestors), None).unwrap();
         assert_eq!(list.slot_list()[idx], (0, true));
-        let ancestors = vec![(1, 0)].into_iter().collect();
+        let ancestors: Ancestors = vec![(1, 0)].into_iter().collect();
         let (list, idx) = index.get(&key.pubkey(), Some(&ancestors), None).unwrap();
         assert_eq!(list.slot_list()[idx], (1, false));
     }
@@ -3060,7 +3669,7 @@ pub mod tests {
 
         // Given a max_root, should filter out roots < max_root, but specified
         // ancestors should not be affected
-        let ancestors = vec![(3, 1), (7, 1)].into_iter().collect();
+        let ancestors: Ancestors = vec![(3, 1), (7, 1)].into_iter().collect();
         assert_eq!(
             index
                 .latest_slot(Some(&ancestors), &slot_slice, Some(4))
@@ -160,6 +160,87 @@ impl Ancestors {
         self.max - self.min
     }
 }
+
+/// Answers whether a slot is an ancestor of some bank. Loads and scans only ever ask about
+/// the slots found in the accounts index, so implementations need not materialize the set.
+pub trait AncestorProvider {
+    fn contains_key(&self, slot: &Slot) -> bool;
+    fn is_empty(&self) -> bool;
+}
+
+impl AncestorProvider for Ancestors {
+    fn contains_key(&self, slot: &Slot) -> bool {
+        Ancestors::contains_key(self, slot)
+    }
+    fn is_empty(&self) -> bool {
+        Ancestors::is_empty(self)
+    }
+}
+
+/// Parent links of the unrooted slots, shared by every fork to resolve ancestry on demand
+#[derive(Debug, Default, Clone)]
+pub struct ForkGraph {
+    parents: HashMap<Slot, Slot>,
+}
+
+impl ForkGraph {
+    pub fn insert(&mut self, slot: Slot, parent: Slot) {
+        self.parents.insert(slot, parent);
+    }
+
+    /// Keep only the links of the descendants of the new `root`, dropping `root`'s own link
+    /// and any fork that didn't descend from it. `root` itself stays an ancestor of its
+    /// descendants, as it is with the `Ancestors` of a squashed bank.
+    pub fn prune(&mut self, root: Slot) {
+        let descendants: HashSet<Slot> = self
+            .parents
+            .keys()
+            .filter(|slot| **slot > root && self.ancestors(**slot).contains_key(&root))
+            .copied()
+            .collect();
+        self.parents
+            .retain(|slot, _parent| descendants.contains(slot));
+    }
+
+    pub fn ancestors(&self, tip: Slot) -> ForkAncestors<'_> {
+        ForkAncestors { graph: self, tip }
+    }
+
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+}
+
+/// The ancestors of `tip`, including `tip`, resolved by walking the parent links of a
+/// `ForkGraph`
+#[derive(Debug, Clone, Copy)]
+pub struct ForkAncestors<'a> {
+    graph: &'a ForkGraph,
+    tip: Slot,
+}
+
+impl<'a> AncestorProvider for ForkAncestors<'a> {
+    fn contains_key(&self, slot: &Slot) -> bool {
+        let mut current = self.tip;
+        // parents are always lower than their children
+        while current > *slot {
+            match self.graph.parents.get(&current) {
+                Some(parent) => current = *parent,
+                None => return false,
+            }
+        }
+        current == *slot
+    }
+
+    fn is_empty(&self) -> bool {
+        false
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -337,4 +418,39 @@ pub mod tests {
             assert_eq!(count, count2);
         }
     }
+
+    #[test]
+    fn test_fork_ancestors() {
+        //        0 (root)
+        //       / \
+        //      2   3
+        //      |   |
+        //      5   4
+        //      |
+        //      7
+        let mut graph = ForkGraph::default();
+        for (slot, parent) in [(2, 0), (3, 0), (4, 3), (5, 2), (7, 5)].iter() {
+            graph.insert(*slot, *parent);
+        }
+        let expected = Ancestors::from(vec![0, 2, 5, 7]);
+        let fork = graph.ancestors(7);
+        for slot in 0..10 {
+            assert_eq!(
+                AncestorProvider::contains_key(&fork, &slot),
+                expected.contains_key(&slot),
+                "slot {}",
+                slot
+            );
+        }
+        assert!(!AncestorProvider::is_empty(&fork));
+
+        // rooting 2 cuts 0 off from 7's fork, as squashing does, and drops the 3-4 fork
+        graph.prune(2);
+        assert_eq!(graph.len(), 2);
+        let fork = graph.ancestors(7);
+        assert!(AncestorProvider::contains_key(&fork, &2));
+        assert!(!AncestorProvider::contains_key(&fork, &0));
+        assert!(AncestorProvider::contains_key(&graph.ancestors(4), &4));
+        assert!(!AncestorProvider::contains_key(&graph.ancestors(4), &3));
+    }
 }
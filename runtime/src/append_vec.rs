@@ -1,8 +1,9 @@
 //! Persistent storage for accounts. For more information, see:
 //! https://docs.solana.com/implemented-proposals/persistent-account-storage
 
+use crate::storage_bloom::StorageBloom;
 use log::*;
-use memmap2::MmapMut;
+use memmap2::{MmapMut, MmapOptions};
 use serde::{Deserialize, Serialize};
 use solana_sdk::{
     account::{Account, AccountSharedData, ReadableAccount},
@@ -12,13 +13,15 @@ use solana_sdk::{
 };
 use std::{
     borrow::Borrow,
-    fs::{remove_file, OpenOptions},
+    collections::{hash_map::Entry, HashMap},
+    convert::TryInto,
+    fs::{remove_file, File, OpenOptions},
     io,
-    io::{Seek, SeekFrom, Write},
+    io::{Read, Seek, SeekFrom, Write},
     mem,
     path::{Path, PathBuf},
-    sync::atomic::{AtomicUsize, Ordering},
-    sync::Mutex,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    sync::{Mutex, MutexGuard},
 };
 
 // Data placement should be aligned at the next boundary. Without alignment accessing the memory may
@@ -32,8 +35,235 @@ macro_rules! u64_align {
 
 const MAXIMUM_APPEND_VEC_FILE_SIZE: usize = 16 * 1024 * 1024 * 1024; // 16 GiB
 
+// Bytes taken up by the fixed size fields preceding the data of each stored account
+const STORED_META_SIZE: usize = u64_align!(mem::size_of::<StoredMeta>())
+    + u64_align!(mem::size_of::<AccountMeta>())
+    + u64_align!(mem::size_of::<Hash>());
+
+// Size of the metadata block at the tail of files created by `new_with_metadata()`. It lives
+// past the accounts, so older versions read those files just fine.
+const METADATA_BLOCK_SIZE: usize = 256;
+const METADATA_MAGIC: &[u8; 8] = b"AVMETA01";
+// Type-length-value entries follow the magic, up to one with this tag
+const METADATA_TAG_END: u16 = 0;
+const METADATA_TAG_CREATION_SLOT: u16 = 1;
+const METADATA_TAG_VALIDATOR_VERSION: u16 = 2;
+const METADATA_TAG_COMPRESSED: u16 = 3;
+const METADATA_TAG_CHECKSUM_ALGORITHM: u16 = 4;
+const METADATA_TAG_STORE_GENERATION: u16 = 5;
+const METADATA_TAG_FORMAT_VERSION: u16 = 6;
+
+/// Format of the accounts in files whose metadata has no format version
+pub const BASE_FORMAT_VERSION: u8 = 1;
+/// Format that lets accounts reference the data of another one, see `DATA_REF_FLAG`
+pub const DATA_REFS_FORMAT_VERSION: u8 = 2;
+
+/// Set in the `data_len` of an account stored by `append_accounts_deduped()` whose data equals
+/// that of an earlier account in the same AppendVec. The offset of that account is stored in
+/// place of the data. Only files whose metadata declares `DATA_REFS_FORMAT_VERSION` hold such
+/// accounts, in any other file the flag is read as part of the length, which is then too
+/// large for the account to be valid. Versions without support for it can't read such files.
+pub const DATA_REF_FLAG: u64 = 1 << 63;
+// Shorter data isn't worth hashing and referencing
+pub const MIN_DEDUP_DATA_LEN: usize = 128;
+
 pub type StoredMetaWriteVersion = u64;
 
+// The account whose data an account appended by `append_accounts_deduped()` references
+#[derive(Clone, Copy)]
+enum DataSource {
+    // at this index of the same append
+    Index(usize),
+    // at this offset, appended before
+    Offset(usize),
+}
+
+/// Why a storage file adopted from elsewhere was rejected, see `AppendVec::validate()`
+#[derive(Debug, Clone, PartialEq)]
+pub enum AppendVecError {
+    /// The file is empty, larger than `MAXIMUM_APPEND_VEC_FILE_SIZE`, or shorter than the
+    /// length claimed for it
+    InvalidFileSize { current_len: usize, file_size: u64 },
+    /// The account at `offset` runs past the claimed length
+    AccountOutOfBounds { offset: usize },
+    /// The account at `offset` has a malformed executable flag, or zero lamports along with
+    /// other non-default fields
+    InvalidAccount { offset: usize },
+    /// The hash stored with the account at `offset` doesn't match its contents
+    HashMismatch { offset: usize, pubkey: Pubkey },
+}
+
+/// A damaged stretch of a storage file that `AppendVec::open_tolerant()` or
+/// `AppendVec::accounts_tolerant()` skipped
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorruptionReport {
+    pub error: AppendVecError,
+    /// Bytes skipped from the offset in `error` on: the account's own size if it was
+    /// skipped alone, or everything up to the claimed length if nothing past it can be trusted
+    pub skipped_bytes: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    None,
+    Sha256,
+    /// Recorded by a newer version
+    Unknown(u8),
+}
+
+impl From<u8> for ChecksumAlgorithm {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => ChecksumAlgorithm::None,
+            1 => ChecksumAlgorithm::Sha256,
+            value => ChecksumAlgorithm::Unknown(value),
+        }
+    }
+}
+
+impl From<ChecksumAlgorithm> for u8 {
+    fn from(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::None => 0,
+            ChecksumAlgorithm::Sha256 => 1,
+            ChecksumAlgorithm::Unknown(value) => value,
+        }
+    }
+}
+
+/// Where a storage file came from and which format features it uses, kept in a block of
+/// type-length-value entries at the end of the file. See `AppendVec::read_metadata()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AppendVecMetadata {
+    /// The slot the file was created, or last recycled, for
+    pub creation_slot: Option<Slot>,
+    /// Version of the validator that created the file
+    pub validator_version: Option<String>,
+    pub compressed: bool,
+    pub checksum_algorithm: ChecksumAlgorithm,
+    /// How often the file was reset to be written anew, see
+    /// `AccountStorageEntry::generation()`
+    pub store_generation: u64,
+    /// Format of the accounts, `DATA_REFS_FORMAT_VERSION` for files that may hold accounts
+    /// referencing the data of another one
+    pub format_version: u8,
+    /// Entries with tags this version doesn't know, kept as they are when rewritten
+    pub unknown_entries: Vec<(u16, Vec<u8>)>,
+}
+
+impl Default for AppendVecMetadata {
+    fn default() -> Self {
+        Self {
+            creation_slot: None,
+            validator_version: None,
+            compressed: false,
+            checksum_algorithm: ChecksumAlgorithm::None,
+            store_generation: 0,
+            format_version: BASE_FORMAT_VERSION,
+            unknown_entries: vec![],
+        }
+    }
+}
+
+impl AppendVecMetadata {
+    /// Metadata for a file created by this validator for `slot`
+    pub fn new(slot: Slot) -> Self {
+        Self {
+            creation_slot: Some(slot),
+            validator_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            ..Self::default()
+        }
+    }
+
+    fn encode(&self) -> io::Result<Vec<u8>> {
+        let mut block = METADATA_MAGIC.to_vec();
+        let mut push_entry = |tag: u16, value: &[u8]| -> io::Result<()> {
+            let len: u16 = value
+                .len()
+                .try_into()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "entry too large"))?;
+            block.extend_from_slice(&tag.to_le_bytes());
+            block.extend_from_slice(&len.to_le_bytes());
+            block.extend_from_slice(value);
+            Ok(())
+        };
+        if let Some(slot) = self.creation_slot {
+            push_entry(METADATA_TAG_CREATION_SLOT, &slot.to_le_bytes())?;
+        }
+        if let Some(version) = &self.validator_version {
+            push_entry(METADATA_TAG_VALIDATOR_VERSION, version.as_bytes())?;
+        }
+        push_entry(METADATA_TAG_COMPRESSED, &[self.compressed as u8])?;
+        push_entry(
+            METADATA_TAG_CHECKSUM_ALGORITHM,
+            &[self.checksum_algorithm.into()],
+        )?;
+        if self.store_generation > 0 {
+            push_entry(
+                METADATA_TAG_STORE_GENERATION,
+                &self.store_generation.to_le_bytes(),
+            )?;
+        }
+        if self.format_version != BASE_FORMAT_VERSION {
+            push_entry(METADATA_TAG_FORMAT_VERSION, &[self.format_version])?;
+        }
+        for (tag, value) in &self.unknown_entries {
+            push_entry(*tag, value)?;
+        }
+        // the end tag is implied by the zeroes that pad the block
+        if block.len() + mem::size_of::<u16>() > METADATA_BLOCK_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("metadata doesn't fit into {} bytes", METADATA_BLOCK_SIZE),
+            ));
+        }
+        block.resize(METADATA_BLOCK_SIZE, 0);
+        Ok(block)
+    }
+
+    fn decode(block: &[u8]) -> Option<Self> {
+        let mut rest = block.strip_prefix(&METADATA_MAGIC[..])?;
+        let mut metadata = Self::default();
+        while rest.len() >= 2 * mem::size_of::<u16>() {
+            let tag = u16::from_le_bytes(rest[0..2].try_into().unwrap());
+            let len = u16::from_le_bytes(rest[2..4].try_into().unwrap()) as usize;
+            if tag == METADATA_TAG_END {
+                break;
+            }
+            let value = rest.get(4..4 + len)?;
+            match tag {
+                METADATA_TAG_CREATION_SLOT => {
+                    metadata.creation_slot = Some(Slot::from_le_bytes(value.try_into().ok()?));
+                }
+                METADATA_TAG_VALIDATOR_VERSION => {
+                    metadata.validator_version = Some(String::from_utf8(value.to_vec()).ok()?);
+                }
+                METADATA_TAG_COMPRESSED => metadata.compressed = *value.first()? != 0,
+                METADATA_TAG_CHECKSUM_ALGORITHM => {
+                    metadata.checksum_algorithm = ChecksumAlgorithm::from(*value.first()?);
+                }
+                METADATA_TAG_STORE_GENERATION => {
+                    metadata.store_generation = u64::from_le_bytes(value.try_into().ok()?);
+                }
+                METADATA_TAG_FORMAT_VERSION => metadata.format_version = *value.first()?,
+                tag => metadata.unknown_entries.push((tag, value.to_vec())),
+            }
+            rest = &rest[4 + len..];
+        }
+        Some(metadata)
+    }
+
+    fn has_data_refs(&self) -> bool {
+        self.format_version >= DATA_REFS_FORMAT_VERSION
+    }
+
+    // Whether `data` ends in a metadata block
+    fn is_at_end_of(data: &[u8]) -> bool {
+        data.len() >= METADATA_BLOCK_SIZE
+            && data[data.len() - METADATA_BLOCK_SIZE..].starts_with(&METADATA_MAGIC[..])
+    }
+}
+
 /// Meta contains enough context to recover the index from storage itself
 /// This struct will be backed by mmaped and snapshotted data files.
 /// So the data layout must be stable and consistent across the entire cluster!
@@ -43,7 +273,21 @@ pub struct StoredMeta {
     pub write_version: StoredMetaWriteVersion,
     /// key for the account
     pub pubkey: Pubkey,
-    pub data_len: u64,
+    /// with `DATA_REF_FLAG` set for an account referencing the data of another one, see
+    /// `data_len()`
+    pub(crate) data_len: u64,
+}
+
+impl StoredMeta {
+    /// Length of the account's data, without `DATA_REF_FLAG`
+    pub fn data_len(&self) -> usize {
+        (self.data_len & !DATA_REF_FLAG) as usize
+    }
+
+    // Whether the account references the data of another one, in a file that allows it
+    fn is_data_ref(&self) -> bool {
+        self.data_len & DATA_REF_FLAG != 0
+    }
 }
 
 /// This struct will be backed by mmaped and snapshotted data files.
@@ -109,6 +353,14 @@ impl<'a> StoredAccountMeta<'a> {
         self.sanitize_executable() && self.sanitize_lamports()
     }
 
+    // A header that was never written to is all zeroes
+    fn is_unwritten(&self) -> bool {
+        self.meta.write_version == 0
+            && self.meta.pubkey == Pubkey::default()
+            && self.account_meta.lamports == 0
+            && *self.hash == Hash::default()
+    }
+
     fn sanitize_executable(&self) -> bool {
         // Sanitize executable to ensure higher 7-bits are cleared correctly.
         self.ref_executable_byte() & !1 == 0
@@ -129,10 +381,12 @@ impl<'a> StoredAccountMeta<'a> {
     }
 }
 
-/// A thread-safe, file-backed block of memory used to store `Account` instances. Append operations
-/// are serialized such that only one thread updates the internal `append_lock` at a time. No
-/// restrictions are placed on reading. That is, one may read items from one thread while another
-/// is appending new items.
+/// A thread-safe, file-backed block of memory used to store `Account` instances. Each append
+/// reserves its byte range up front, copies its items into that range and then publishes them
+/// by advancing `current_len`. `append_accounts()` serializes appenders through `append_lock`,
+/// while `append_accounts_concurrent()` lets several threads copy into the same AppendVec at
+/// once. No restrictions are placed on reading. That is, one may read items from one thread
+/// while another is appending new items.
 #[derive(Debug, AbiExample)]
 pub struct AppendVec {
     /// The file path where the data is stored.
@@ -147,22 +401,36 @@ pub struct AppendVec {
     /// The number of bytes used to store items, not the number of items.
     current_len: AtomicUsize,
 
+    /// The number of bytes handed out to appenders. Runs ahead of `current_len`
+    /// while reserved ranges are still being written.
+    reserved_len: AtomicUsize,
+
     /// The number of bytes available for storing items.
     file_size: u64,
 
     /// True if the file should automatically be deleted when this AppendVec is dropped.
-    remove_on_drop: bool,
+    remove_on_drop: AtomicBool,
+
+    /// True if the metadata declares `DATA_REFS_FORMAT_VERSION`, so that `DATA_REF_FLAG` is
+    /// read as such
+    data_refs: AtomicBool,
+
+    /// Digests of the data appended by `append_accounts_deduped()`, with the offset of the
+    /// account holding it. Lives as long as the AppendVec, so identical data is stored once per
+    /// file, whichever append it came with.
+    data_digests: Mutex<HashMap<[u8; 32], usize>>,
 }
 
 impl Drop for AppendVec {
     fn drop(&mut self) {
-        if self.remove_on_drop {
+        if self.remove_on_drop.load(Ordering::Acquire) {
             if let Err(_e) = remove_file(&self.path) {
                 // promote this to panic soon.
                 // disabled due to many false positive warnings while running tests.
                 // blocked by rpc's upgrade to jsonrpc v17
                 //error!("AppendVec failed to remove {:?}: {:?}", &self.path, e);
             }
+            StorageBloom::remove(&self.path);
         }
     }
 }
@@ -218,13 +486,104 @@ impl AppendVec {
             // See UNSAFE usage in `append_ptr`
             append_lock: Mutex::new(()),
             current_len: AtomicUsize::new(initial_len),
+            reserved_len: AtomicUsize::new(initial_len),
             file_size: size as u64,
-            remove_on_drop: true,
+            remove_on_drop: AtomicBool::new(true),
+            data_refs: AtomicBool::new(false),
+            data_digests: Mutex::default(),
+        }
+    }
+
+    /// Like `new()` for a new file, with `metadata` written into a block past the `size`
+    /// bytes available for accounts
+    pub fn new_with_metadata(file: &Path, size: usize, metadata: &AppendVecMetadata) -> Self {
+        let block = metadata.encode().unwrap();
+        let mut append_vec = AppendVec::new(file, true, size + METADATA_BLOCK_SIZE);
+        append_vec.file_size = size as u64;
+        append_vec.write_metadata_block(&block);
+        append_vec.data_refs = AtomicBool::new(metadata.has_data_refs());
+        append_vec
+    }
+
+    /// The metadata of a file created by `new_with_metadata()`
+    pub fn metadata(&self) -> Option<AppendVecMetadata> {
+        let start = self.file_size as usize;
+        self.map
+            .get(start..start + METADATA_BLOCK_SIZE)
+            .and_then(AppendVecMetadata::decode)
+    }
+
+    /// Whether the metadata declares `DATA_REFS_FORMAT_VERSION`, so the file may hold accounts
+    /// that versions without support for references can't read
+    pub fn declares_data_refs(&self) -> bool {
+        self.data_refs.load(Ordering::Acquire)
+    }
+
+    /// Replaces the metadata of a file created by `new_with_metadata()`. Must not race with
+    /// readers of the metadata.
+    pub fn set_metadata(&self, metadata: &AppendVecMetadata) -> io::Result<()> {
+        if self.metadata().is_none() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                "storage file has no metadata block",
+            ));
+        }
+        self.write_metadata_block(&metadata.encode()?);
+        self.data_refs
+            .store(metadata.has_data_refs(), Ordering::Release);
+        Ok(())
+    }
+
+    // Whether the metadata block past the `file_size` bytes available for accounts declares
+    // `DATA_REFS_FORMAT_VERSION`
+    fn has_data_refs(map: &[u8], file_size: u64) -> bool {
+        let start = file_size as usize;
+        map.get(start..start + METADATA_BLOCK_SIZE)
+            .and_then(AppendVecMetadata::decode)
+            .map_or(false, |metadata| metadata.has_data_refs())
+    }
+
+    fn write_metadata_block(&self, block: &[u8]) {
+        let start = self.file_size as usize;
+        let data = &self.map[start..start + METADATA_BLOCK_SIZE];
+        //UNSAFE: The metadata block lies past everything handed out to appenders, and
+        //callers make sure nobody reads it meanwhile
+        unsafe {
+            let dst = data.as_ptr() as *mut u8;
+            std::ptr::copy(block.as_ptr(), dst, METADATA_BLOCK_SIZE);
+        };
+    }
+
+    /// The metadata at the end of the storage file at `path`, read without mapping or
+    /// parsing the rest of the file. `None` for files without any.
+    pub fn read_metadata<P: AsRef<Path>>(path: P) -> io::Result<Option<AppendVecMetadata>> {
+        let mut file = File::open(path)?;
+        if file.metadata()?.len() < METADATA_BLOCK_SIZE as u64 {
+            return Ok(None);
         }
+        file.seek(SeekFrom::End(-(METADATA_BLOCK_SIZE as i64)))?;
+        let mut block = vec![0; METADATA_BLOCK_SIZE];
+        file.read_exact(&mut block)?;
+        Ok(AppendVecMetadata::decode(&block))
     }
 
-    pub fn set_no_remove_on_drop(&mut self) {
-        self.remove_on_drop = false;
+    // The number of bytes of `map` available for accounts, leaving out a metadata block.
+    // A file is only taken to have one if the accounts claimed to be in it don't overlap it.
+    fn accounts_size(map: &[u8], current_len: usize) -> u64 {
+        if AppendVecMetadata::is_at_end_of(map) && current_len <= map.len() - METADATA_BLOCK_SIZE {
+            (map.len() - METADATA_BLOCK_SIZE) as u64
+        } else {
+            map.len() as u64
+        }
+    }
+
+    pub fn set_no_remove_on_drop(&self) {
+        self.remove_on_drop.store(false, Ordering::Release);
+    }
+
+    /// Undoes `set_no_remove_on_drop()`
+    pub fn set_remove_on_drop(&self) {
+        self.remove_on_drop.store(true, Ordering::Release);
     }
 
     pub fn new_empty_map(current_len: usize) -> Self {
@@ -242,8 +601,11 @@ impl AppendVec {
             map,
             append_lock: Mutex::new(()),
             current_len: AtomicUsize::new(current_len),
+            reserved_len: AtomicUsize::new(current_len),
             file_size: 0, // will be filled by set_file()
-            remove_on_drop: true,
+            remove_on_drop: AtomicBool::new(true),
+            data_refs: AtomicBool::new(false),
+            data_digests: Mutex::default(),
         }
     }
 
@@ -272,11 +634,67 @@ impl AppendVec {
         self.map.flush()
     }
 
+    /// Must not race with `append_accounts_concurrent()`
     pub fn reset(&self) {
         // This mutex forces append to be single threaded, but concurrent with reads
         // See UNSAFE usage in `append_ptr`
         let _lock = self.append_lock.lock().unwrap();
+        self.reserved_len.store(0, Ordering::Relaxed);
+        self.current_len.store(0, Ordering::Relaxed);
+        self.data_digests.lock().unwrap().clear();
+    }
+
+    /// `reset()`, clearing the bytes that were handed out to appenders so that writing over
+    /// them later neither faults in stale pages nor leaves stale accounts past `len()`. With
+    /// `punch_hole` the range is deallocated from the file where the file system supports
+    /// it, which frees the disk space too, and zeroed otherwise. Must not race with readers
+    /// or appenders. Returns the number of bytes cleared.
+    pub fn reset_and_clear(&self, punch_hole: bool) -> io::Result<usize> {
+        let _lock = self.append_lock.lock().unwrap();
+        let len = std::cmp::min(
+            u64_align!(self.reserved_len.load(Ordering::Acquire)),
+            self.file_size as usize,
+        );
+        if len > 0 && !(punch_hole && self.punch_hole(len)?) {
+            let data = &self.map[..len];
+            //UNSAFE: the caller guarantees nobody else touches the range, like `append_ptr`
+            unsafe {
+                std::ptr::write_bytes(data.as_ptr() as *mut u8, 0, len);
+            }
+        }
+        self.reserved_len.store(0, Ordering::Relaxed);
         self.current_len.store(0, Ordering::Relaxed);
+        self.data_digests.lock().unwrap().clear();
+        Ok(len)
+    }
+
+    /// Deallocates the first `len` bytes of the file, returning false if the file system
+    /// can't
+    #[cfg(target_os = "linux")]
+    fn punch_hole(&self, len: usize) -> io::Result<bool> {
+        use std::os::unix::io::AsRawFd;
+        let file = OpenOptions::new().write(true).open(&self.path)?;
+        let result = unsafe {
+            libc::fallocate(
+                file.as_raw_fd(),
+                libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                0,
+                len as libc::off_t,
+            )
+        };
+        if result == 0 {
+            return Ok(true);
+        }
+        let err = io::Error::last_os_error();
+        match err.raw_os_error() {
+            Some(libc::EOPNOTSUPP) => Ok(false),
+            _ => Err(err),
+        }
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn punch_hole(&self, _len: usize) -> io::Result<bool> {
+        Ok(false)
     }
 
     pub fn len(&self) -> usize {
@@ -287,47 +705,241 @@ impl AppendVec {
         self.len() == 0
     }
 
+    /// The bytes at `offset..offset + len`, as they are in the file, if all of them have been
+    /// written
+    pub fn written_bytes(&self, offset: usize, len: usize) -> Option<&[u8]> {
+        self.get_slice_within(offset, len, self.len())
+            .map(|(data, _next)| data)
+    }
+
     pub fn capacity(&self) -> u64 {
         self.file_size
     }
 
+    /// The number of bytes that can still be reserved by appenders
+    pub fn remaining_bytes(&self) -> u64 {
+        self.capacity()
+            .saturating_sub(u64_align!(self.reserved_len.load(Ordering::Acquire)) as u64)
+    }
+
+    /// The number of bytes an account with `data_len` bytes of data takes up when stored,
+    /// including alignment padding
+    pub fn calculate_stored_size(data_len: usize) -> usize {
+        u64_align!(STORED_META_SIZE + data_len)
+    }
+
     pub fn file_name(slot: Slot, id: usize) -> String {
         format!("{}.{}", slot, id)
     }
 
+    /// Inverse of `file_name`
+    pub fn parse_file_name(file_name: &str) -> Option<(Slot, usize)> {
+        let mut parts = file_name.splitn(2, '.');
+        let slot = parts.next()?.parse().ok()?;
+        let id = parts.next()?.parse().ok()?;
+        Some((slot, id))
+    }
+
     pub fn new_from_file<P: AsRef<Path>>(path: P, current_len: usize) -> io::Result<(Self, usize)> {
+        let file_size = std::fs::metadata(&path)?.len();
+        AppendVec::sanitize_len_and_size(current_len, file_size as usize)?;
+
+        let new = Self::new_from_file_unchecked(path, current_len)?;
+        let num_accounts = new.validate().map_err(|_| {
+            std::io::Error::new(std::io::ErrorKind::Other, "incorrect layout/length/data")
+        })?;
+
+        Ok((new, num_accounts))
+    }
+
+    /// Map an existing storage file claimed to hold `current_len` bytes of accounts without
+    /// looking at its contents. Nothing may be read from it before `validate()` passed.
+    pub fn new_from_file_unchecked<P: AsRef<Path>>(
+        path: P,
+        current_len: usize,
+    ) -> io::Result<Self> {
         let data = OpenOptions::new()
             .read(true)
             .write(true)
             .create(false)
             .open(&path)?;
 
-        let file_size = std::fs::metadata(&path)?.len();
-        AppendVec::sanitize_len_and_size(current_len, file_size as usize)?;
-
         let map = unsafe { MmapMut::map_mut(&data)? };
+        let file_size = Self::accounts_size(&map, current_len);
 
-        let new = AppendVec {
+        Ok(AppendVec {
             path: path.as_ref().to_path_buf(),
-            map,
             append_lock: Mutex::new(()),
             current_len: AtomicUsize::new(current_len),
+            reserved_len: AtomicUsize::new(current_len),
+            data_refs: AtomicBool::new(Self::has_data_refs(&map, file_size)),
+            map,
             file_size,
-            remove_on_drop: true,
-        };
+            remove_on_drop: AtomicBool::new(true),
+            data_digests: Mutex::default(),
+        })
+    }
+
+    /// Open a storage file owned by another process, which may still be appending to it.
+    /// Nothing is ever written to or removed through the returned AppendVec. It starts out
+    /// empty; use `scan_written_len` and `extend_len` to expose what has been written so far.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let data = OpenOptions::new().read(true).open(&path)?;
+        let file_size = data.metadata()?.len();
+        AppendVec::sanitize_len_and_size(0, file_size as usize)?;
+
+        // a private mapping of a read-only file: pages that are never written through it keep
+        // reflecting the owner's writes
+        let map = unsafe { MmapOptions::new().map_copy(&data)? };
+        let file_size = Self::accounts_size(&map, 0);
 
-        let (sanitized, num_accounts) = new.sanitize_layout_and_length();
-        if !sanitized {
+        Ok(AppendVec {
+            path: path.as_ref().to_path_buf(),
+            append_lock: Mutex::new(()),
+            current_len: AtomicUsize::new(0),
+            reserved_len: AtomicUsize::new(0),
+            data_refs: AtomicBool::new(Self::has_data_refs(&map, file_size)),
+            map,
+            file_size,
+            remove_on_drop: AtomicBool::new(false),
+            data_digests: Mutex::default(),
+        })
+    }
+
+    /// Open a possibly damaged storage file for forensics, without rejecting it, writing to it
+    /// or removing it. Accounts are read up to `current_len`, or up to the first header that is
+    /// still zeroed when the length isn't known. The returned AppendVec's `len()` ends where
+    /// the last account whose size could be trusted ends, and `accounts_tolerant()` reads the
+    /// accounts up to there. What had to be skipped is reported.
+    pub fn open_tolerant<P: AsRef<Path>>(
+        path: P,
+        current_len: Option<usize>,
+    ) -> io::Result<(Self, Vec<CorruptionReport>)> {
+        let data = OpenOptions::new().read(true).open(&path)?;
+        let file_size = data.metadata()?.len();
+        if file_size == 0 {
             return Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "incorrect layout/length/data",
+                std::io::ErrorKind::InvalidData,
+                "empty storage file",
             ));
         }
+        let map = unsafe { MmapOptions::new().map_copy(&data)? };
+        let file_size = Self::accounts_size(&map, current_len.unwrap_or_default());
+        let append_vec = AppendVec {
+            path: path.as_ref().to_path_buf(),
+            append_lock: Mutex::new(()),
+            current_len: AtomicUsize::new(0),
+            reserved_len: AtomicUsize::new(0),
+            data_refs: AtomicBool::new(Self::has_data_refs(&map, file_size)),
+            map,
+            file_size,
+            remove_on_drop: AtomicBool::new(false),
+            data_digests: Mutex::default(),
+        };
 
-        Ok((new, num_accounts))
+        let mut reports = vec![];
+        let (limit, stop_at_unwritten) = match current_len {
+            Some(current_len) if current_len as u64 > file_size => {
+                // a length that doesn't even fit the file is as good as unknown
+                reports.push(CorruptionReport {
+                    error: AppendVecError::InvalidFileSize {
+                        current_len,
+                        file_size,
+                    },
+                    skipped_bytes: current_len - file_size as usize,
+                });
+                (file_size as usize, true)
+            }
+            Some(current_len) => (current_len, false),
+            None => (file_size as usize, true),
+        };
+        let (_accounts, walk_reports, end) = append_vec.walk_tolerant(limit, stop_at_unwritten);
+        reports.extend(walk_reports);
+        append_vec.extend_len(end);
+        Ok((append_vec, reports))
     }
 
-    fn sanitize_layout_and_length(&self) -> (bool, usize) {
+    /// Like `accounts(0)`, but accounts that fail to sanitize are skipped and reported instead
+    /// of being returned, so that nothing reads their malformed fields.
+    pub fn accounts_tolerant(&self) -> (Vec<StoredAccountMeta<'_>>, Vec<CorruptionReport>) {
+        let (accounts, reports, _end) = self.walk_tolerant(self.len(), false);
+        (accounts, reports)
+    }
+
+    // Walks the accounts up to `limit`, skipping the ones that fail to sanitize. An account
+    // running past `limit` ends the walk, since the offset of the next one can't be trusted.
+    // Returns the accounts, what was skipped and where the walk ended.
+    fn walk_tolerant(
+        &self,
+        limit: usize,
+        stop_at_unwritten: bool,
+    ) -> (Vec<StoredAccountMeta<'_>>, Vec<CorruptionReport>, usize) {
+        let mut accounts = vec![];
+        let mut reports = vec![];
+        let mut offset = 0;
+        while offset < limit {
+            let (account, next_offset) = match self.get_account_within(offset, limit) {
+                Some(found) => found,
+                None => {
+                    reports.push(CorruptionReport {
+                        error: AppendVecError::AccountOutOfBounds { offset },
+                        skipped_bytes: limit - offset,
+                    });
+                    break;
+                }
+            };
+            if stop_at_unwritten && account.is_unwritten() {
+                break;
+            }
+            if account.sanitize() {
+                accounts.push(account);
+            } else {
+                reports.push(CorruptionReport {
+                    error: AppendVecError::InvalidAccount { offset },
+                    skipped_bytes: account.stored_size,
+                });
+            }
+            offset = next_offset;
+        }
+        (accounts, reports, std::cmp::min(offset, limit))
+    }
+
+    /// Walk the accounts written past `len()` and return the offset just past the last of
+    /// them, or `len()` if there are none. The walk stops at the first header that is still
+    /// zeroed or fails to sanitize, so this is only meaningful for AppendVecs opened by
+    /// `open_read_only`, or for telling whether a file was appended to since it was last seen.
+    pub fn scan_written_len(&self) -> usize {
+        let limit = self.file_size as usize;
+        let mut written_len = self.len();
+        let mut offset = u64_align!(written_len);
+        while let Some((account, next_offset)) = self.get_account_within(offset, limit) {
+            if account.is_unwritten() || !account.sanitize() {
+                break;
+            }
+            offset = next_offset;
+            written_len = next_offset;
+        }
+        written_len
+    }
+
+    /// Make the accounts up to `len` visible to readers. `len` must be the end of an account
+    /// previously found by `scan_written_len`.
+    pub fn extend_len(&self, len: usize) {
+        assert!(len <= self.file_size as usize);
+        self.reserved_len.fetch_max(len, Ordering::AcqRel);
+        self.current_len.fetch_max(len, Ordering::Release);
+    }
+
+    /// Check that the file can hold the length claimed for it and that the accounts up to
+    /// that length are in bounds and sane. Returns the number of accounts.
+    pub fn validate(&self) -> Result<usize, AppendVecError> {
+        let current_len = self.current_len.load(Ordering::Relaxed);
+        AppendVec::sanitize_len_and_size(current_len, self.file_size as usize).map_err(|_| {
+            AppendVecError::InvalidFileSize {
+                current_len,
+                file_size: self.file_size,
+            }
+        })?;
         let mut offset = 0;
 
         // This discards allocated accounts immediately after check at each loop iteration.
@@ -338,23 +950,25 @@ impl AppendVec {
         let mut num_accounts = 0;
         while let Some((account, next_offset)) = self.get_account(offset) {
             if !account.sanitize() {
-                return (false, num_accounts);
+                return Err(AppendVecError::InvalidAccount { offset });
             }
             offset = next_offset;
             num_accounts += 1;
         }
-        let aligned_current_len = u64_align!(self.current_len.load(Ordering::Relaxed));
+        if offset != u64_align!(current_len) {
+            return Err(AppendVecError::AccountOutOfBounds { offset });
+        }
 
-        (offset == aligned_current_len, num_accounts)
+        Ok(num_accounts)
     }
 
     /// Get a reference to the data at `offset` of `size` bytes if that slice
-    /// doesn't overrun the internal buffer. Otherwise return None.
+    /// doesn't overrun `limit`. Otherwise return None.
     /// Also return the offset of the first byte after the requested data that
     /// falls on a 64-byte boundary.
-    fn get_slice(&self, offset: usize, size: usize) -> Option<(&[u8], usize)> {
+    fn get_slice_within(&self, offset: usize, size: usize, limit: usize) -> Option<(&[u8], usize)> {
         let (next, overflow) = offset.overflowing_add(size);
-        if overflow || next > self.len() {
+        if overflow || next > limit {
             return None;
         }
         let data = &self.map[offset..next];
@@ -373,9 +987,9 @@ impl AppendVec {
     fn append_ptr(&self, offset: &mut usize, src: *const u8, len: usize) {
         let pos = u64_align!(*offset);
         let data = &self.map[pos..(pos + len)];
-        //UNSAFE: This mut append is safe because only 1 thread can append at a time
-        //Mutex<()> guarantees exclusive write access to the memory occupied in
-        //the range.
+        //UNSAFE: This mut append is safe because the range was handed out by `reserve()`
+        //to exactly one appender, which guarantees exclusive write access to the memory
+        //occupied in the range.
         unsafe {
             let dst = data.as_ptr() as *mut u8;
             std::ptr::copy(src, dst, len);
@@ -383,34 +997,58 @@ impl AppendVec {
         *offset = pos + len;
     }
 
-    /// Copy each value in `vals`, in order, to the first 64-byte boundary after position `offset`.
-    /// If there is sufficient space, then update `offset` and the internal `current_len` to the
-    /// first byte after the copied data and return the starting position of the copied data.
-    /// Otherwise return None and leave `offset` unchanged.
-    fn append_ptrs_locked(&self, offset: &mut usize, vals: &[(*const u8, usize)]) -> Option<usize> {
-        let mut end = *offset;
-        for val in vals {
-            end = u64_align!(end);
-            end += val.1;
-        }
-
-        if (self.file_size as usize) < end {
-            return None;
+    /// Reserve room for as many of the leading items in `items` as fit into the remaining
+    /// space. Each item's values are laid out the same way `append_ptr` lays them out.
+    /// Return the start of the reserved range and the number of items it holds.
+    fn reserve(&self, items: &[[(*const u8, usize); 4]]) -> (usize, usize) {
+        let mut start = self.reserved_len.load(Ordering::Acquire);
+        loop {
+            let mut end = start;
+            let mut num_items = 0;
+            for item in items {
+                let mut item_end = end;
+                for val in item {
+                    item_end = u64_align!(item_end) + val.1;
+                }
+                if (self.file_size as usize) < item_end {
+                    break;
+                }
+                end = item_end;
+                num_items += 1;
+            }
+            if num_items == 0 {
+                return (start, 0);
+            }
+            match self.reserved_len.compare_exchange_weak(
+                start,
+                end,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return (start, num_items),
+                Err(current) => start = current,
+            }
         }
+    }
 
-        let pos = u64_align!(*offset);
-        for val in vals {
-            self.append_ptr(offset, val.0, val.1)
+    /// Make the written range `[start, end)` visible to readers. Readers walk the
+    /// AppendVec front to back, so this waits until all earlier reservations have
+    /// been published to avoid exposing any unwritten gaps.
+    fn publish(&self, start: usize, end: usize) {
+        while self
+            .current_len
+            .compare_exchange_weak(start, end, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            std::hint::spin_loop();
         }
-        self.current_len.store(*offset, Ordering::Relaxed);
-        Some(pos)
     }
 
     /// Return a reference to the type at `offset` if its data doesn't overrun the internal buffer.
     /// Otherwise return None. Also return the offset of the first byte after the requested data
     /// that falls on a 64-byte boundary.
-    fn get_type<'a, T>(&self, offset: usize) -> Option<(&'a T, usize)> {
-        let (data, next) = self.get_slice(offset, mem::size_of::<T>())?;
+    fn get_type<'a, T>(&self, offset: usize, limit: usize) -> Option<(&'a T, usize)> {
+        let (data, next) = self.get_slice_within(offset, mem::size_of::<T>(), limit)?;
         let ptr: *const T = data.as_ptr() as *const T;
         //UNSAFE: The cast is safe because the slice is aligned and fits into the memory
         //and the lifetime of the &T is tied to self, which holds the underlying memory map
@@ -421,10 +1059,31 @@ impl AppendVec {
     /// the internal buffer. Otherwise return None. Also return the offset of the first byte
     /// after the requested data that falls on a 64-byte boundary.
     pub fn get_account<'a>(&'a self, offset: usize) -> Option<(StoredAccountMeta<'a>, usize)> {
-        let (meta, next): (&'a StoredMeta, _) = self.get_type(offset)?;
-        let (account_meta, next): (&'a AccountMeta, _) = self.get_type(next)?;
-        let (hash, next): (&'a Hash, _) = self.get_type(next)?;
-        let (data, next) = self.get_slice(next, meta.data_len as usize)?;
+        self.get_account_within(offset, self.len())
+    }
+
+    fn get_account_within<'a>(
+        &'a self,
+        offset: usize,
+        limit: usize,
+    ) -> Option<(StoredAccountMeta<'a>, usize)> {
+        let (meta, next): (&'a StoredMeta, _) = self.get_type(offset, limit)?;
+        let (account_meta, next): (&'a AccountMeta, _) = self.get_type(next, limit)?;
+        let (hash, next): (&'a Hash, _) = self.get_type(next, limit)?;
+        let (data, next) = if !meta.is_data_ref() || !self.data_refs.load(Ordering::Acquire) {
+            self.get_slice_within(next, meta.data_len as usize, limit)?
+        } else {
+            let (source_offset, next): (&'a u64, _) = self.get_type(next, limit)?;
+            let source_offset = *source_offset as usize;
+            if source_offset >= offset {
+                return None;
+            }
+            let (source, _) = self.get_account_within(source_offset, offset)?;
+            if source.meta.data_len() != meta.data_len() || source.meta.is_data_ref() {
+                return None;
+            }
+            (source.data, next)
+        };
         let stored_size = next - offset;
         Some((
             StoredAccountMeta {
@@ -460,44 +1119,180 @@ impl AppendVec {
 
     /// Copy each account metadata, account and hash to the internal buffer.
     /// Return the starting offset of each account metadata.
-    /// After each account is appended, the internal `current_len` is updated
-    /// and will be available to other threads.
+    /// After the accounts are appended, the internal `current_len` is updated
+    /// and they will be available to other threads.
     pub fn append_accounts(
         &self,
         accounts: &[(StoredMeta, Option<&impl ReadableAccount>)],
         hashes: &[impl Borrow<Hash>],
     ) -> Vec<usize> {
         let _lock = self.append_lock.lock().unwrap();
-        let mut offset = self.len();
-        let mut rv = Vec::with_capacity(accounts.len());
-        for ((stored_meta, account), hash) in accounts.iter().zip(hashes) {
-            let meta_ptr = stored_meta as *const StoredMeta;
-            let account_meta = AccountMeta::from(*account);
-            let account_meta_ptr = &account_meta as *const AccountMeta;
-            let data_len = stored_meta.data_len as usize;
-            let data_ptr = account
+        self.append_accounts_concurrent(accounts, hashes)
+    }
+
+    /// Keep other `append_accounts()` callers out of this AppendVec while the guard is held.
+    /// Lets a caller check `remaining_bytes()` and then split a batch across several
+    /// `append_accounts_concurrent()` calls without another appender taking the room.
+    pub fn lock_appends(&self) -> MutexGuard<'_, ()> {
+        self.append_lock.lock().unwrap()
+    }
+
+    /// Same as `append_accounts()`, but may be called by multiple threads at once on the
+    /// same AppendVec. The offsets returned for one call are contiguous, but calls that
+    /// overlap in time are placed in the file in the order they reserved their space.
+    pub fn append_accounts_concurrent(
+        &self,
+        accounts: &[(StoredMeta, Option<&impl ReadableAccount>)],
+        hashes: &[impl Borrow<Hash>],
+    ) -> Vec<usize> {
+        self.append_items(accounts, hashes, &[]).0
+    }
+
+    /// Same as `append_accounts()`, but an account whose data equals that of an account appended
+    /// earlier the same way, by this call or a previous one, stores a reference to it instead of
+    /// another copy. Readers get the data of the referenced account. Also returns the number of
+    /// data bytes saved. Appends plain copies unless the metadata declares
+    /// `DATA_REFS_FORMAT_VERSION`.
+    pub fn append_accounts_deduped(
+        &self,
+        accounts: &[(StoredMeta, Option<&impl ReadableAccount>)],
+        hashes: &[impl Borrow<Hash>],
+    ) -> (Vec<usize>, usize) {
+        let _lock = self.append_lock.lock().unwrap();
+        if !self.data_refs.load(Ordering::Acquire) {
+            return self.append_items(accounts, hashes, &[]);
+        }
+        let data = |i: usize| {
+            accounts[i]
+                .1
                 .map(|account| account.data())
                 .unwrap_or_default()
-                .as_ptr();
-            let hash_ptr = hash.borrow().as_ref().as_ptr();
-            let ptrs = [
-                (meta_ptr as *const u8, mem::size_of::<StoredMeta>()),
-                (account_meta_ptr as *const u8, mem::size_of::<AccountMeta>()),
-                (hash_ptr as *const u8, mem::size_of::<Hash>()),
-                (data_ptr, data_len),
-            ];
-            if let Some(res) = self.append_ptrs_locked(&mut offset, &ptrs) {
-                rv.push(res)
-            } else {
-                break;
+        };
+        let mut data_digests = self.data_digests.lock().unwrap();
+        let mut first_by_digest = HashMap::new();
+        let sources: Vec<Option<DataSource>> = (0..accounts.len())
+            .map(|i| {
+                if data(i).len() < MIN_DEDUP_DATA_LEN {
+                    return None;
+                }
+                let digest = *blake3::hash(data(i)).as_bytes();
+                // a digest collision only costs a copy
+                if let Some(offset) = data_digests.get(&digest) {
+                    return Some(DataSource::Offset(*offset)).filter(|_| {
+                        self.get_account(*offset)
+                            .map_or(false, |(source, _)| source.data == data(i))
+                    });
+                }
+                match first_by_digest.entry(digest) {
+                    Entry::Occupied(first) => Some(DataSource::Index(*first.get()))
+                        .filter(|_| data(*first.get()) == data(i)),
+                    Entry::Vacant(first) => {
+                        first.insert(i);
+                        None
+                    }
+                }
+            })
+            .collect();
+        let (rv, saved_bytes) = self.append_items(accounts, hashes, &sources);
+        // `rv` ends with the offset past the accounts that fit
+        for (digest, i) in first_by_digest {
+            if i + 1 < rv.len() {
+                data_digests.insert(digest, rv[i]);
             }
         }
+        (rv, saved_bytes)
+    }
+
+    // Appends the accounts that fit. An account with a `sources` entry stores the offset of the
+    // account it names in place of its data.
+    fn append_items(
+        &self,
+        accounts: &[(StoredMeta, Option<&impl ReadableAccount>)],
+        hashes: &[impl Borrow<Hash>],
+        sources: &[Option<DataSource>],
+    ) -> (Vec<usize>, usize) {
+        let source = |i: usize| sources.get(i).copied().flatten();
+        let account_metas: Vec<AccountMeta> = accounts
+            .iter()
+            .map(|(_, account)| AccountMeta::from(*account))
+            .collect();
+        let ref_metas: Vec<Option<StoredMeta>> = accounts
+            .iter()
+            .enumerate()
+            .map(|(i, (stored_meta, _))| {
+                source(i).map(|_| StoredMeta {
+                    data_len: stored_meta.data_len | DATA_REF_FLAG,
+                    ..stored_meta.clone()
+                })
+            })
+            .collect();
+        let items: Vec<_> = accounts
+            .iter()
+            .zip(&account_metas)
+            .zip(&ref_metas)
+            .zip(hashes)
+            .map(
+                |((((stored_meta, account), account_meta), ref_meta), hash)| {
+                    let data_ptr = account
+                        .map(|account| account.data())
+                        .unwrap_or_default()
+                        .as_ptr();
+                    let (stored_meta, data) = match ref_meta {
+                        // the pointer to the source offset is only known once it's appended
+                        Some(ref_meta) => (ref_meta, (std::ptr::null(), mem::size_of::<u64>())),
+                        None => (stored_meta, (data_ptr, stored_meta.data_len())),
+                    };
+                    [
+                        (
+                            stored_meta as *const StoredMeta as *const u8,
+                            mem::size_of::<StoredMeta>(),
+                        ),
+                        (
+                            account_meta as *const AccountMeta as *const u8,
+                            mem::size_of::<AccountMeta>(),
+                        ),
+                        (hash.borrow().as_ref().as_ptr(), mem::size_of::<Hash>()),
+                        data,
+                    ]
+                },
+            )
+            .collect();
+
+        let (start, num_items) = self.reserve(&items);
+        let mut offset = start;
+        let mut rv = Vec::with_capacity(num_items + 1);
+        let mut saved_bytes = 0;
+        for (i, item) in items[..num_items].iter().enumerate() {
+            rv.push(u64_align!(offset));
+            let (data, rest) = item.split_last().unwrap();
+            for val in rest {
+                self.append_ptr(&mut offset, val.0, val.1);
+            }
+            match source(i) {
+                Some(source) => {
+                    let source_offset = match source {
+                        DataSource::Index(index) => rv[index],
+                        DataSource::Offset(offset) => offset,
+                    } as u64;
+                    self.append_ptr(
+                        &mut offset,
+                        &source_offset as *const u64 as *const u8,
+                        data.1,
+                    );
+                    saved_bytes += u64_align!(accounts[i].0.data_len()) - data.1;
+                }
+                None => self.append_ptr(&mut offset, data.0, data.1),
+            }
+        }
+        if num_items > 0 {
+            self.publish(start, offset);
+        }
 
         // The last entry in this offset needs to be the u64 aligned offset, because that's
         // where the *next* entry will begin to be stored.
         rv.push(u64_align!(offset));
 
-        rv
+        (rv, saved_bytes)
     }
 
     /// Copy the account metadata, account and hash to the internal buffer.
@@ -573,7 +1368,7 @@ pub mod tests {
     use assert_matches::assert_matches;
     use rand::{thread_rng, Rng};
     use solana_sdk::{account::WritableAccount, timing::duration_as_ms};
-    use std::time::Instant;
+    use std::{sync::Arc, time::Instant};
 
     impl AppendVec {
         fn append_account_test(&self, data: &(StoredMeta, AccountSharedData)) -> Option<usize> {
@@ -767,11 +1562,58 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_append_vec_append_concurrent() {
+        let path = get_append_vec_path("test_append_concurrent");
+        let num_threads = 4;
+        let accounts_per_thread = 100;
+        let samples: Vec<_> = (0..num_threads * accounts_per_thread)
+            .map(create_test_account)
+            .collect();
+        let total_size: usize = samples
+            .iter()
+            .map(|(_, account)| AppendVec::calculate_stored_size(account.data().len()))
+            .sum();
+        let av = Arc::new(AppendVec::new(&path.path, true, total_size));
+
+        let handles: Vec<_> = samples
+            .chunks(accounts_per_thread)
+            .map(|chunk| {
+                let av = av.clone();
+                let chunk = chunk.to_vec();
+                std::thread::spawn(move || {
+                    let accounts: Vec<_> = chunk
+                        .iter()
+                        .map(|(meta, account)| (meta.clone(), Some(account)))
+                        .collect();
+                    let hashes = vec![Hash::default(); accounts.len()];
+                    av.append_accounts_concurrent(&accounts, &hashes)
+                })
+            })
+            .collect();
+        let offsets: Vec<Vec<usize>> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+
+        // Everything fit exactly, and each thread's accounts can be read back
+        assert_eq!(av.remaining_bytes(), 0);
+        assert_eq!(u64_align!(av.len()), total_size);
+        for (chunk, rvs) in samples.chunks(accounts_per_thread).zip(offsets) {
+            assert_eq!(rvs.len(), chunk.len() + 1);
+            for (sample, offset) in chunk.iter().zip(rvs) {
+                assert_eq!(av.get_account_test(offset).unwrap(), *sample);
+            }
+        }
+        assert_eq!(av.accounts(0).len(), samples.len());
+
+        // No room left for anything else
+        let sample = create_test_account(0);
+        assert!(av.append_account_test(&sample).is_none());
+    }
+
     #[test]
     fn test_new_from_file_crafted_zero_lamport_account() {
         let file = get_append_vec_path("test_append");
         let path = &file.path;
-        let mut av = AppendVec::new(&path, true, 1024 * 1024);
+        let av = AppendVec::new(&path, true, 1024 * 1024);
         av.set_no_remove_on_drop();
 
         let pubkey = solana_sdk::pubkey::new_rand();
@@ -791,6 +1633,13 @@ pub mod tests {
         av.flush().unwrap();
         let accounts_len = av.len();
         drop(av);
+        let av = AppendVec::new_from_file_unchecked(path, accounts_len).unwrap();
+        av.set_no_remove_on_drop();
+        assert_eq!(
+            av.validate(),
+            Err(AppendVecError::InvalidAccount { offset: 0 })
+        );
+        drop(av);
         let result = AppendVec::new_from_file(path, accounts_len);
         assert_matches!(result, Err(ref message) if message.to_string() == *"incorrect layout/length/data");
     }
@@ -799,7 +1648,7 @@ pub mod tests {
     fn test_new_from_file_crafted_data_len() {
         let file = get_append_vec_path("test_new_from_file_crafted_data_len");
         let path = &file.path;
-        let mut av = AppendVec::new(&path, true, 1024 * 1024);
+        let av = AppendVec::new(&path, true, 1024 * 1024);
         av.set_no_remove_on_drop();
 
         let crafted_data_len = 1;
@@ -827,7 +1676,7 @@ pub mod tests {
     fn test_new_from_file_too_large_data_len() {
         let file = get_append_vec_path("test_new_from_file_too_large_data_len");
         let path = &file.path;
-        let mut av = AppendVec::new(&path, true, 1024 * 1024);
+        let av = AppendVec::new(&path, true, 1024 * 1024);
         av.set_no_remove_on_drop();
 
         let too_large_data_len = u64::max_value();
@@ -845,15 +1694,286 @@ pub mod tests {
         av.flush().unwrap();
         let accounts_len = av.len();
         drop(av);
+        let av = AppendVec::new_from_file_unchecked(path, accounts_len).unwrap();
+        av.set_no_remove_on_drop();
+        assert_eq!(
+            av.validate(),
+            Err(AppendVecError::AccountOutOfBounds { offset: 0 })
+        );
+        drop(av);
         let result = AppendVec::new_from_file(path, accounts_len);
         assert_matches!(result, Err(ref message) if message.to_string() == *"incorrect layout/length/data");
     }
 
+    #[test]
+    fn test_validate() {
+        let file = get_append_vec_path("test_validate");
+        let path = &file.path;
+        let av = AppendVec::new(&path, true, 1024 * 1024);
+        av.set_no_remove_on_drop();
+        av.append_account_test(&create_test_account(10)).unwrap();
+        av.append_account_test(&create_test_account(11)).unwrap();
+        av.flush().unwrap();
+        let accounts_len = av.len();
+        drop(av);
+
+        let av = AppendVec::new_from_file_unchecked(path, accounts_len).unwrap();
+        av.set_no_remove_on_drop();
+        assert_eq!(av.validate(), Ok(2));
+        drop(av);
+
+        // too short for another account past what was written
+        let av = AppendVec::new_from_file_unchecked(path, accounts_len + 8).unwrap();
+        av.set_no_remove_on_drop();
+        assert_eq!(
+            av.validate(),
+            Err(AppendVecError::AccountOutOfBounds {
+                offset: u64_align!(accounts_len)
+            })
+        );
+        drop(av);
+
+        let av = AppendVec::new_from_file_unchecked(path, 2 * 1024 * 1024).unwrap();
+        av.set_no_remove_on_drop();
+        assert_eq!(
+            av.validate(),
+            Err(AppendVecError::InvalidFileSize {
+                current_len: 2 * 1024 * 1024,
+                file_size: 1024 * 1024,
+            })
+        );
+    }
+
+    #[test]
+    fn test_reset_and_clear() {
+        for punch_hole in [false, true].iter().copied() {
+            let file = get_append_vec_path("test_reset_and_clear");
+            let av = AppendVec::new(&file.path, true, 1024 * 1024);
+            av.append_account_test(&create_test_account(10)).unwrap();
+            av.append_account_test(&create_test_account(11)).unwrap();
+            let written_len = av.len();
+            assert!(av.map[..written_len].iter().any(|byte| *byte != 0));
+
+            av.reset_and_clear(punch_hole).unwrap();
+            assert!(av.is_empty());
+            assert_eq!(av.remaining_bytes(), av.capacity());
+            assert!(av.map[..written_len].iter().all(|byte| *byte == 0));
+            // nothing stale is found past the end either
+            assert_eq!(av.scan_written_len(), 0);
+            assert_eq!(av.append_account_test(&create_test_account(12)).unwrap(), 0);
+        }
+    }
+
+    #[test]
+    fn test_append_vec_metadata() {
+        let file = get_append_vec_path("test_append_vec_metadata");
+        let path = &file.path;
+        let mut metadata = AppendVecMetadata::new(7);
+        metadata.unknown_entries.push((99, vec![1, 2, 3]));
+        let size = 64 * 1024;
+        let av = AppendVec::new_with_metadata(path, size, &metadata);
+        av.set_no_remove_on_drop();
+        assert_eq!(av.capacity(), size as u64);
+        assert_eq!(av.metadata(), Some(metadata.clone()));
+        // appends can't reach into the block
+        while av.append_account_test(&create_test_account(1000)).is_some() {}
+        av.flush().unwrap();
+        assert_eq!(
+            AppendVec::read_metadata(path).unwrap(),
+            Some(metadata.clone())
+        );
+
+        metadata.creation_slot = Some(8);
+        metadata.checksum_algorithm = ChecksumAlgorithm::Sha256;
+        metadata.store_generation = 3;
+        av.set_metadata(&metadata).unwrap();
+        let accounts_len = av.len();
+        drop(av);
+        let (av, _num_accounts) = AppendVec::new_from_file(path, accounts_len).unwrap();
+        assert_eq!(av.capacity(), size as u64);
+        assert_eq!(av.metadata(), Some(metadata));
+
+        metadata = AppendVecMetadata::new(0);
+        metadata.validator_version = Some("v".repeat(METADATA_BLOCK_SIZE));
+        assert!(metadata.encode().is_err());
+
+        // files without a block
+        let file = get_append_vec_path("test_append_vec_metadata_none");
+        let av = AppendVec::new(&file.path, true, size);
+        assert_eq!(av.metadata(), None);
+        assert!(av.set_metadata(&AppendVecMetadata::default()).is_err());
+        assert_eq!(AppendVec::read_metadata(&file.path).unwrap(), None);
+    }
+
+    #[test]
+    fn test_append_accounts_deduped() {
+        let file = get_append_vec_path("test_append_accounts_deduped");
+        let path = &file.path;
+        let mut metadata = AppendVecMetadata::new(0);
+        metadata.format_version = DATA_REFS_FORMAT_VERSION;
+        let av = AppendVec::new_with_metadata(path, 1024 * 1024, &metadata);
+        av.set_no_remove_on_drop();
+        // 200 and 201 share their data, as do 455 and 456 with 200
+        let accounts: Vec<_> = [200, 201, 456, 455, 100, 100]
+            .iter()
+            .map(|sample| {
+                let (mut meta, mut account) = create_test_account(*sample);
+                if *sample != 100 {
+                    account.set_data(vec![7; 200]);
+                    meta.data_len = 200;
+                }
+                (meta, account)
+            })
+            .collect();
+        let to_store: Vec<_> = accounts
+            .iter()
+            .map(|(meta, account)| (meta.clone(), Some(account)))
+            .collect();
+        let hashes = vec![Hash::default(); 3];
+        // 455 references 200 from a later append
+        let (mut offsets, saved_bytes) = av.append_accounts_deduped(&to_store[..3], &hashes);
+        assert_eq!(saved_bytes, 2 * (200 - 8));
+        offsets.pop();
+        let (more_offsets, saved_bytes) = av.append_accounts_deduped(&to_store[3..], &hashes);
+        // data shorter than MIN_DEDUP_DATA_LEN is copied
+        assert_eq!(saved_bytes, 200 - 8);
+        offsets.extend(more_offsets);
+        assert_eq!(offsets.len(), accounts.len() + 1);
+
+        let check = |av: &AppendVec| {
+            let stored = av.accounts(0);
+            assert_eq!(stored.len(), accounts.len());
+            for (i, (stored, (meta, account))) in stored.iter().zip(&accounts).enumerate() {
+                assert_eq!(stored.offset, offsets[i]);
+                assert_eq!(stored.clone_account(), *account);
+                assert_eq!(stored.meta.is_data_ref(), [1, 2, 3].contains(&i));
+                assert_eq!(stored.meta.data_len(), meta.data_len as usize);
+            }
+        };
+        check(&av);
+        let accounts_len = av.len();
+        drop(av);
+        let (av, num_accounts) = AppendVec::new_from_file(path, accounts_len).unwrap();
+        assert_eq!(num_accounts, accounts.len());
+        check(&av);
+
+        // a reference must point back to an account with data of the same length
+        let ref_data = av.map[offsets[1] + STORED_META_SIZE..].as_ptr() as *mut u64;
+        unsafe {
+            assert_eq!(*ref_data, offsets[0] as u64);
+            *ref_data = offsets[4] as u64;
+        }
+        assert!(av.get_account(offsets[1]).is_none());
+
+        // without the format version the flag is taken as part of the length
+        metadata.format_version = BASE_FORMAT_VERSION;
+        av.set_metadata(&metadata).unwrap();
+        assert!(av.get_account(offsets[2]).is_none());
+        assert_eq!(av.accounts(0).len(), 1);
+    }
+
+    #[test]
+    fn test_append_accounts_deduped_base_format() {
+        let file = get_append_vec_path("test_append_accounts_deduped_base_format");
+        let av = AppendVec::new_with_metadata(&file.path, 1024 * 1024, &AppendVecMetadata::new(0));
+        let mut account = AccountSharedData::new(1, 200, &Pubkey::default());
+        account.set_data(vec![7; 200]);
+        let (mut meta, _) = create_test_account(0);
+        meta.data_len = 200;
+        let to_store = vec![(meta.clone(), Some(&account)), (meta, Some(&account))];
+        let (offsets, saved_bytes) =
+            av.append_accounts_deduped(&to_store, &[Hash::default(), Hash::default()]);
+        assert_eq!(saved_bytes, 0);
+        let stored = av.accounts(0);
+        assert_eq!(stored.len(), 2);
+        assert!(!stored[1].meta.is_data_ref());
+        assert_eq!(stored[1].offset, offsets[1]);
+    }
+
+    #[test]
+    fn test_open_tolerant() {
+        let file = get_append_vec_path("test_open_tolerant");
+        let path = &file.path;
+        let av = AppendVec::new(&path, true, 1024 * 1024);
+        av.set_no_remove_on_drop();
+        for sample in 10..13 {
+            av.append_account_test(&create_test_account(sample))
+                .unwrap();
+        }
+        let accounts = av.accounts(0);
+        let corrupt = &accounts[1];
+        corrupt.set_executable_as_byte(2);
+        let invalid_account = CorruptionReport {
+            error: AppendVecError::InvalidAccount {
+                offset: corrupt.offset,
+            },
+            skipped_bytes: corrupt.stored_size,
+        };
+        let good_offsets = vec![accounts[0].offset, accounts[2].offset];
+        av.flush().unwrap();
+        let accounts_len = av.len();
+        drop(av);
+        let offsets = |av: &AppendVec| {
+            let (accounts, reports) = av.accounts_tolerant();
+            assert_eq!(reports, vec![invalid_account.clone()]);
+            accounts
+                .iter()
+                .map(|account| account.offset)
+                .collect::<Vec<_>>()
+        };
+
+        let (av, reports) = AppendVec::open_tolerant(path, Some(accounts_len)).unwrap();
+        assert_eq!(reports, vec![invalid_account.clone()]);
+        assert_eq!(av.len(), accounts_len);
+        assert_eq!(offsets(&av), good_offsets);
+        drop(av);
+
+        // without a length the accounts are read up to the zeroed remainder of the file
+        let (av, reports) = AppendVec::open_tolerant(path, None).unwrap();
+        assert_eq!(reports, vec![invalid_account.clone()]);
+        assert_eq!(av.len(), u64_align!(accounts_len));
+        assert_eq!(offsets(&av), good_offsets);
+        drop(av);
+
+        // a truncated account ends what can be read
+        let (av, reports) = AppendVec::open_tolerant(path, Some(accounts_len + 8)).unwrap();
+        assert_eq!(
+            reports,
+            vec![
+                invalid_account.clone(),
+                CorruptionReport {
+                    error: AppendVecError::AccountOutOfBounds {
+                        offset: u64_align!(accounts_len)
+                    },
+                    skipped_bytes: accounts_len + 8 - u64_align!(accounts_len),
+                }
+            ]
+        );
+        assert_eq!(offsets(&av), good_offsets);
+        drop(av);
+
+        let (av, reports) = AppendVec::open_tolerant(path, Some(2 * 1024 * 1024)).unwrap();
+        assert_eq!(
+            reports[0],
+            CorruptionReport {
+                error: AppendVecError::InvalidFileSize {
+                    current_len: 2 * 1024 * 1024,
+                    file_size: 1024 * 1024,
+                },
+                skipped_bytes: 1024 * 1024,
+            }
+        );
+        assert_eq!(offsets(&av), good_offsets);
+        drop(av);
+        // nothing was removed
+        assert!(path.exists());
+    }
+
     #[test]
     fn test_new_from_file_crafted_executable() {
         let file = get_append_vec_path("test_new_from_crafted_executable");
         let path = &file.path;
-        let mut av = AppendVec::new(&path, true, 1024 * 1024);
+        let av = AppendVec::new(&path, true, 1024 * 1024);
         av.set_no_remove_on_drop();
         av.append_account_test(&create_test_account(10)).unwrap();
         {
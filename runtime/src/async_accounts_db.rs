@@ -0,0 +1,271 @@
+//! An async facade over AccountsDb loads, for RPC. Loads block, at times for long while clean
+//! and shrink churn through the storages, so they run on a dedicated pool of blocking threads
+//! instead of the executor's. The futures returned are plain `std::future::Future`s, which
+//! tokio or any other executor can await.
+
+use crate::{
+    accounts_db::{AccountsDb, LoadError, LoadHint},
+    ancestors::Ancestors,
+};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use solana_sdk::{account::AccountSharedData, clock::Slot, pubkey::Pubkey};
+use std::{
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    time::Instant,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum AsyncLoadError {
+    #[error("too many loads queued, at most {0} are")]
+    QueueFull(usize),
+    #[error("the deadline passed while the load was queued")]
+    DeadlineExpired,
+    #[error("load failed: {0:?}")]
+    Load(LoadError),
+    #[error("the load panicked")]
+    Panicked,
+}
+
+impl From<LoadError> for AsyncLoadError {
+    fn from(err: LoadError) -> Self {
+        AsyncLoadError::Load(err)
+    }
+}
+
+struct TaskState<T> {
+    result: Option<Result<T, AsyncLoadError>>,
+    waker: Option<Waker>,
+}
+
+// Gives back the queue slot of a closure once it's done, whether it returned or panicked
+struct QueueSlot(Arc<AtomicUsize>);
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Resolves to what a closure passed to `AsyncAccountsDb::spawn()` returned, or to
+/// `AsyncLoadError::Panicked` if it panicked. Dropping it doesn't stop the closure, whose
+/// result is then dropped.
+pub struct BlockingTask<T> {
+    state: Arc<Mutex<TaskState<T>>>,
+}
+
+impl<T> Future for BlockingTask<T> {
+    type Output = Result<T, AsyncLoadError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+pub struct AsyncAccountsDb {
+    accounts_db: Arc<AccountsDb>,
+    thread_pool: Arc<ThreadPool>,
+    max_queued: usize,
+    // queued or running on the pool
+    num_queued: Arc<AtomicUsize>,
+}
+
+impl AsyncAccountsDb {
+    /// Loads run on a new pool of `num_threads` threads. Beyond `max_queued` loads queued or
+    /// running, more fail right away with `AsyncLoadError::QueueFull`.
+    pub fn new(accounts_db: Arc<AccountsDb>, num_threads: usize, max_queued: usize) -> Self {
+        let thread_pool = ThreadPoolBuilder::new()
+            .thread_name(|i| format!("solana-accounts-db-async-{}", i))
+            .num_threads(num_threads)
+            .build()
+            .unwrap();
+        Self::new_with_thread_pool(accounts_db, Arc::new(thread_pool), max_queued)
+    }
+
+    /// Like `new()`, but loads run on `thread_pool`, e.g. the pool RPC scans run on
+    pub fn new_with_thread_pool(
+        accounts_db: Arc<AccountsDb>,
+        thread_pool: Arc<ThreadPool>,
+        max_queued: usize,
+    ) -> Self {
+        Self {
+            accounts_db,
+            thread_pool,
+            max_queued,
+            num_queued: Arc::default(),
+        }
+    }
+
+    /// The pool loads run on, to run scans and other blocking work alongside them
+    pub fn thread_pool(&self) -> &Arc<ThreadPool> {
+        &self.thread_pool
+    }
+
+    /// Number of loads and other closures queued or running on the pool
+    pub fn num_queued(&self) -> usize {
+        self.num_queued.load(Ordering::Relaxed)
+    }
+
+    /// Queues `f` on the pool right away, counting against the same bound as loads, e.g. to
+    /// run a scan. The returned future resolves to what `f` returns. A panic in `f` is caught,
+    /// so it fails this task rather than the process.
+    pub fn spawn<F, T>(&self, f: F) -> Result<BlockingTask<T>, AsyncLoadError>
+    where
+        F: FnOnce(&AccountsDb) -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        if self.num_queued.fetch_add(1, Ordering::Relaxed) >= self.max_queued {
+            self.num_queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(AsyncLoadError::QueueFull(self.max_queued));
+        }
+        let state = Arc::new(Mutex::new(TaskState {
+            result: None,
+            waker: None,
+        }));
+        let task = BlockingTask {
+            state: state.clone(),
+        };
+        let accounts_db = self.accounts_db.clone();
+        let num_queued = self.num_queued.clone();
+        self.thread_pool.spawn(move || {
+            let queue_slot = QueueSlot(num_queued);
+            let result = panic::catch_unwind(AssertUnwindSafe(|| f(&accounts_db)))
+                .map_err(|_| AsyncLoadError::Panicked);
+            drop(queue_slot);
+            let waker = {
+                let mut state = state.lock().unwrap();
+                state.result = Some(result);
+                state.waker.take()
+            };
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        });
+        Ok(task)
+    }
+
+    /// Like `AccountsDb::try_load()`, or `AccountsDb::load_with_deadline()` given a deadline.
+    /// A load still queued when the deadline passes fails without being attempted.
+    pub async fn load(
+        &self,
+        ancestors: Ancestors,
+        pubkey: Pubkey,
+        deadline: Option<Instant>,
+    ) -> Result<Option<(AccountSharedData, Slot)>, AsyncLoadError> {
+        self.spawn(move |accounts_db| match deadline {
+            Some(deadline) => {
+                if Instant::now() >= deadline {
+                    return Err(AsyncLoadError::DeadlineExpired);
+                }
+                Ok(accounts_db.load_with_deadline(&ancestors, &pubkey, deadline)?)
+            }
+            None => Ok(accounts_db.try_load(&ancestors, &pubkey, LoadHint::Unspecified)?),
+        })?
+        .await?
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crossbeam_channel::unbounded;
+    use std::{
+        task::Wake,
+        thread::{self, Thread},
+        time::Duration,
+    };
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn test_async_accounts_db_load() {
+        let accounts_db = Arc::new(AccountsDb::new_single());
+        let pubkey = Pubkey::new_unique();
+        let account = AccountSharedData::new(1, 0, &Pubkey::default());
+        accounts_db.store_uncached(0, &[(&pubkey, &account)]);
+        accounts_db.add_root(0);
+        let async_accounts_db = AsyncAccountsDb::new(accounts_db, 1, 1);
+        let ancestors = Ancestors::default();
+
+        assert_eq!(
+            block_on(async_accounts_db.load(ancestors.clone(), pubkey, None)),
+            Ok(Some((account.clone(), 0)))
+        );
+        assert_eq!(
+            block_on(async_accounts_db.load(ancestors.clone(), Pubkey::new_unique(), None)),
+            Ok(None)
+        );
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert_eq!(
+            block_on(async_accounts_db.load(ancestors.clone(), pubkey, Some(deadline))),
+            Ok(Some((account.clone(), 0)))
+        );
+        assert_eq!(
+            block_on(async_accounts_db.load(ancestors.clone(), pubkey, Some(Instant::now()))),
+            Err(AsyncLoadError::DeadlineExpired)
+        );
+
+        // while the only slot is taken, loads are turned away
+        let (sender, receiver) = unbounded();
+        let blocker = async_accounts_db
+            .spawn(move |_accounts_db| receiver.recv().unwrap())
+            .unwrap();
+        assert_eq!(async_accounts_db.num_queued(), 1);
+        assert_eq!(
+            block_on(async_accounts_db.load(ancestors.clone(), pubkey, None)),
+            Err(AsyncLoadError::QueueFull(1))
+        );
+        sender.send(42).unwrap();
+        assert_eq!(block_on(blocker), Ok(42));
+        assert_eq!(async_accounts_db.num_queued(), 0);
+        assert_eq!(
+            block_on(async_accounts_db.load(ancestors, pubkey, None)),
+            Ok(Some((account, 0)))
+        );
+    }
+
+    #[test]
+    fn test_async_accounts_db_spawn_panic() {
+        let async_accounts_db = AsyncAccountsDb::new(Arc::new(AccountsDb::new_single()), 1, 1);
+        let task = async_accounts_db
+            .spawn(|_accounts_db| -> u64 { panic!("load panicked") })
+            .unwrap();
+        assert_eq!(block_on(task), Err(AsyncLoadError::Panicked));
+        assert_eq!(async_accounts_db.num_queued(), 0);
+        // the pool survives it
+        let task = async_accounts_db.spawn(|_accounts_db| 42).unwrap();
+        assert_eq!(block_on(task), Ok(42));
+    }
+}
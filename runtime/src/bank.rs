@@ -38,7 +38,10 @@ use crate::{
         AccountAddressFilter, Accounts, TransactionAccountDeps, TransactionAccounts,
         TransactionLoadResult, TransactionLoaders,
     },
-    accounts_db::{ErrorCounters, SnapshotStorages},
+    accounts_db::{
+        AccountFilter, CleanEstimate, CleanPhase, CleanSession, ErrorCounters, IndexScanError,
+        IndexScanOptions, SnapshotStorages, VersionedBankHashStats,
+    },
     accounts_index::{AccountSecondaryIndexes, IndexKey},
     ancestors::{Ancestors, AncestorsForSerialization},
     blockhash_queue::BlockhashQueue,
@@ -1069,7 +1072,8 @@ impl Bank {
         // Freeze accounts after process_genesis_config creates the initial append vecs
         Arc::get_mut(&mut Arc::get_mut(&mut bank.rc.accounts).unwrap().accounts_db)
             .unwrap()
-            .freeze_accounts(&bank.ancestors, frozen_account_pubkeys);
+            .freeze_accounts(&bank.ancestors, frozen_account_pubkeys)
+            .unwrap_or_else(|err| panic!("{}", err));
 
         // genesis needs stakes for all epochs up to the epoch implied by
         //  slot = 0 and genesis configuration
@@ -2657,6 +2661,10 @@ impl Bank {
         self.rc.accounts.accounts_db.set_shrink_paths(paths);
     }
 
+    pub fn set_capacity_paths(&self, paths: Vec<PathBuf>) {
+        self.rc.accounts.accounts_db.set_capacity_paths(paths);
+    }
+
     fn check_age<'a>(
         &self,
         txs: impl Iterator<Item = &'a Transaction>,
@@ -4352,6 +4360,44 @@ impl Bank {
             .load_by_index_key_with_filter(&self.ancestors, index_key, filter)
     }
 
+    /// Accounts owned by `program_id` matching every one of `filters`, which are evaluated
+    /// before account data is loaded
+    pub fn get_program_accounts_matching(
+        &self,
+        program_id: &Pubkey,
+        filters: &[AccountFilter],
+    ) -> Vec<(Pubkey, AccountSharedData)> {
+        self.rc
+            .accounts
+            .load_by_program_matching(&self.ancestors, program_id, filters)
+    }
+
+    pub fn get_indexed_accounts_matching(
+        &self,
+        index_key: &IndexKey,
+        filters: &[AccountFilter],
+    ) -> Vec<(Pubkey, AccountSharedData)> {
+        self.rc
+            .accounts
+            .load_by_index_key_matching(&self.ancestors, index_key, filters)
+    }
+
+    /// Like `get_indexed_accounts_matching()`, with `options` to reject unindexed keys and
+    /// to leave out accounts clean hasn't yet removed from the index
+    pub fn get_indexed_accounts_with_options(
+        &self,
+        index_key: &IndexKey,
+        filters: &[AccountFilter],
+        options: IndexScanOptions,
+    ) -> std::result::Result<Vec<(Pubkey, AccountSharedData)>, IndexScanError> {
+        self.rc.accounts.load_by_index_key_with_options(
+            &self.ancestors,
+            index_key,
+            filters,
+            options,
+        )
+    }
+
     pub fn account_indexes_include_key(&self, key: &Pubkey) -> bool {
         self.rc.accounts.account_indexes_include_key(key)
     }
@@ -4593,6 +4639,15 @@ impl Bank {
         self.rc.accounts.accounts_db.get_accounts_hash(self.slot)
     }
 
+    /// The stats of the accounts this bank stored, see
+    /// `AccountsDb::versioned_bank_hash_stats()`
+    pub fn bank_hash_stats(&self) -> Option<VersionedBankHashStats> {
+        self.rc
+            .accounts
+            .accounts_db
+            .versioned_bank_hash_stats(self.slot)
+    }
+
     pub fn get_thread_pool(&self) -> &ThreadPool {
         &self.rc.accounts.accounts_db.thread_pool_clean
     }
@@ -4912,8 +4967,8 @@ impl Bank {
             .add_program(program_id, process_instruction_with_context);
     }
 
-    pub fn clean_accounts(&self, skip_last: bool, is_startup: bool) {
-        let max_clean_slot = if skip_last {
+    fn max_clean_slot(&self, skip_last: bool) -> Option<Slot> {
+        if skip_last {
             // Don't clean the slot we're snapshotting because it may have zero-lamport
             // accounts that were included in the bank delta hash when the bank was frozen,
             // and if we clean them here, any newly created snapshot's hash for this bank
@@ -4921,11 +4976,35 @@ impl Bank {
             Some(self.slot().saturating_sub(1))
         } else {
             None
-        };
+        }
+    }
+
+    pub fn clean_accounts(&self, skip_last: bool, is_startup: bool) {
         self.rc
             .accounts
             .accounts_db
-            .clean_accounts(max_clean_slot, is_startup);
+            .clean_accounts(self.max_clean_slot(skip_last), is_startup);
+    }
+
+    /// Whether `clean_accounts(skip_last)` would likely do anything, see
+    /// `AccountsDb::clean_needed()`
+    pub fn clean_needed(&self, skip_last: bool) -> CleanEstimate {
+        self.rc
+            .accounts
+            .accounts_db
+            .clean_needed(self.max_clean_slot(skip_last))
+    }
+
+    /// Like `clean_accounts()`, but the clean is carried out by `clean_step()` calls
+    pub fn start_clean_session(&self, skip_last: bool) -> CleanSession {
+        self.rc
+            .accounts
+            .accounts_db
+            .start_clean_session(self.max_clean_slot(skip_last), false)
+    }
+
+    pub fn clean_step(&self, session: &mut CleanSession, max_keys: usize) -> CleanPhase {
+        self.rc.accounts.accounts_db.clean_step(session, max_keys)
     }
 
     pub fn shrink_all_slots(&self, is_startup: bool) {
@@ -7023,7 +7102,7 @@ pub(crate) mod tests {
                 .accounts
                 .accounts_db
                 .accounts_index
-                .get(&pubkey, Some(&ancestors), None)
+                .get(&pubkey, Some(ancestors), None)
                 .unwrap();
             locked_entry
                 .slot_list()
@@ -1,4 +1,5 @@
 //! Simple Bloom Filter
+use crate::contains::MaybeContains;
 use bv::BitVec;
 use fnv::FnvHasher;
 use rand::{self, Rng};
@@ -132,6 +133,12 @@ impl<T: AsRef<[u8]>> BloomHashIndex for T {
     }
 }
 
+impl<T: BloomHashIndex> MaybeContains<T> for Bloom<T> {
+    fn maybe_contains(&self, key: &T) -> bool {
+        self.contains(key)
+    }
+}
+
 pub struct AtomicBloom<T> {
     num_bits: u64,
     keys: Vec<u64>,
@@ -196,6 +203,12 @@ impl<T: BloomHashIndex> AtomicBloom<T> {
     }
 }
 
+impl<T: BloomHashIndex> MaybeContains<T> for AtomicBloom<T> {
+    fn maybe_contains(&self, key: &T) -> bool {
+        self.contains(key)
+    }
+}
+
 impl<T: BloomHashIndex> From<AtomicBloom<T>> for Bloom<T> {
     fn from(atomic_bloom: AtomicBloom<T>) -> Self {
         let bits: Vec<_> = atomic_bloom
@@ -47,3 +47,21 @@ impl<'a, T: 'a + Eq + Hash + Copy> Contains<'a, T> for T {
         std::iter::once(self)
     }
 }
+
+/// Membership test that may return false positives, but never false negatives,
+/// e.g. a bloom filter. Unlike `Contains`, the members can't be enumerated.
+pub trait MaybeContains<T> {
+    fn maybe_contains(&self, key: &T) -> bool;
+}
+
+impl<T: Eq + Hash> MaybeContains<T> for HashSet<T> {
+    fn maybe_contains(&self, key: &T) -> bool {
+        self.contains(key)
+    }
+}
+
+impl<T: Eq + Hash, U> MaybeContains<T> for HashMap<T, U> {
+    fn maybe_contains(&self, key: &T) -> bool {
+        self.contains_key(key)
+    }
+}
@@ -0,0 +1,178 @@
+//! Finds the accounts loaded most often. Loads are counted in a count-min sketch, so tracking
+//! every load costs a few atomic increments and a fixed amount of memory, however many
+//! accounts there are. The counts are halved every `DECAY_INTERVAL` loads so they reflect
+//! recent traffic. The hot accounts are kept in a sharded map, so loads of different hot
+//! accounts don't contend on a lock.
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+use std::{
+    convert::TryInto,
+    sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering},
+};
+
+const SKETCH_DEPTH: usize = 4;
+const SKETCH_WIDTH: usize = 4096;
+const DECAY_INTERVAL: u64 = 1 << 20;
+// Keeps the bookkeeping, and whatever is pinned because of it, bounded
+pub const MAX_HOT_ACCOUNTS: usize = 256;
+
+#[derive(Debug)]
+pub struct HotAccounts {
+    counters: Vec<AtomicU32>,
+    loads: AtomicU64,
+    threshold: u32,
+    // the newest version loaded of each hot account
+    hot: DashMap<Pubkey, Slot>,
+    num_hot: AtomicUsize,
+}
+
+impl HotAccounts {
+    /// Accounts are hot once they were loaded about `threshold` times since the last decay
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            counters: (0..SKETCH_DEPTH * SKETCH_WIDTH)
+                .map(|_| AtomicU32::default())
+                .collect(),
+            loads: AtomicU64::default(),
+            threshold,
+            hot: DashMap::default(),
+            num_hot: AtomicUsize::default(),
+        }
+    }
+
+    // Pubkeys are hashes or curve points already, so each row indexes by another 8 bytes
+    fn counter_indexes(pubkey: &Pubkey) -> impl Iterator<Item = usize> + '_ {
+        pubkey
+            .as_ref()
+            .chunks_exact(8)
+            .enumerate()
+            .map(|(row, chunk)| {
+                let column = u64::from_le_bytes(chunk.try_into().unwrap()) as usize % SKETCH_WIDTH;
+                row * SKETCH_WIDTH + column
+            })
+    }
+
+    /// Counts a load of the version of `pubkey` at `slot`. Returns whether that version
+    /// should be pinned, as the account is hot and it's the newest version loaded, along with
+    /// the versions to unpin: the one it replaces, and those of the accounts that cooled down
+    /// if this load triggered a decay.
+    pub fn record_load(&self, pubkey: &Pubkey, slot: Slot) -> (bool, Vec<(Pubkey, Slot)>) {
+        let estimate = Self::counter_indexes(pubkey)
+            .map(|index| self.counters[index].fetch_add(1, Ordering::Relaxed) + 1)
+            .min()
+            .unwrap_or_default();
+        let mut unpin = vec![];
+        let pin = estimate >= self.threshold
+            && self
+                .hot
+                .get(pubkey)
+                .map(|hot_slot| *hot_slot < slot)
+                .unwrap_or(true)
+            && match self.hot.entry(*pubkey) {
+                Entry::Occupied(mut entry) => {
+                    let newer = *entry.get() < slot;
+                    if newer {
+                        unpin.push((*pubkey, entry.insert(slot)));
+                    }
+                    newer
+                }
+                Entry::Vacant(entry) => {
+                    let has_room = self.num_hot.fetch_add(1, Ordering::Relaxed) < MAX_HOT_ACCOUNTS;
+                    if has_room {
+                        entry.insert(slot);
+                    } else {
+                        self.num_hot.fetch_sub(1, Ordering::Relaxed);
+                    }
+                    has_room
+                }
+            };
+
+        let cooled = if (self.loads.fetch_add(1, Ordering::Relaxed) + 1) % DECAY_INTERVAL == 0 {
+            self.decay()
+        } else {
+            vec![]
+        };
+        unpin.extend(cooled);
+        (pin, unpin)
+    }
+
+    // Halves every count and drops the hot accounts that fell below the threshold
+    fn decay(&self) -> Vec<(Pubkey, Slot)> {
+        for counter in &self.counters {
+            // racing increments may get lost, which the estimate can live with
+            counter.store(counter.load(Ordering::Relaxed) / 2, Ordering::Relaxed);
+        }
+        let mut cooled = vec![];
+        self.hot.retain(|pubkey, slot| {
+            let hot = self.estimate(pubkey) >= self.threshold;
+            if !hot {
+                cooled.push((*pubkey, *slot));
+            }
+            hot
+        });
+        self.num_hot.fetch_sub(cooled.len(), Ordering::Relaxed);
+        cooled
+    }
+
+    /// Loads of `pubkey` since the last decay, possibly overestimated because of collisions
+    pub fn estimate(&self, pubkey: &Pubkey) -> u32 {
+        Self::counter_indexes(pubkey)
+            .map(|index| self.counters[index].load(Ordering::Relaxed))
+            .min()
+            .unwrap_or_default()
+    }
+
+    pub fn is_hot(&self, pubkey: &Pubkey) -> bool {
+        self.hot.contains_key(pubkey)
+    }
+
+    /// The `top_n` hot accounts with their estimated loads, most loaded first
+    pub fn top(&self, top_n: usize) -> Vec<(Pubkey, u32)> {
+        let mut hot: Vec<_> = self
+            .hot
+            .iter()
+            .map(|entry| (*entry.key(), self.estimate(entry.key())))
+            .collect();
+        hot.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        hot.truncate(top_n);
+        hot
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hot_accounts() {
+        let hot_accounts = HotAccounts::new(3);
+        let keys: Vec<_> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        for (i, key) in keys.iter().enumerate() {
+            for _ in 0..(i + 2) {
+                hot_accounts.record_load(key, 0);
+            }
+        }
+        assert!(!hot_accounts.is_hot(&keys[0]));
+        assert!(hot_accounts.is_hot(&keys[1]));
+        assert!(hot_accounts.estimate(&keys[2]) >= 4);
+        assert_eq!(hot_accounts.top(1), vec![(keys[2], 4)]);
+        assert_eq!(hot_accounts.top(5).len(), 2);
+        assert_eq!(hot_accounts.record_load(&keys[0], 0), (true, vec![]));
+        assert_eq!(hot_accounts.record_load(&keys[0], 0), (false, vec![]));
+        // a newer version replaces the pinned one, an older one doesn't
+        assert_eq!(
+            hot_accounts.record_load(&keys[0], 2),
+            (true, vec![(keys[0], 0)])
+        );
+        assert_eq!(hot_accounts.record_load(&keys[0], 1), (false, vec![]));
+
+        let mut cooled = hot_accounts.decay();
+        cooled.sort();
+        let mut expected = vec![(keys[1], 0), (keys[2], 0)];
+        expected.sort();
+        // 3 and 4 loads halve to below the threshold, 6 don't
+        assert_eq!(cooled, expected);
+        assert_eq!(hot_accounts.top(5), vec![(keys[0], 3)]);
+    }
+}
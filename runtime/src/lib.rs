@@ -8,6 +8,7 @@ pub mod accounts_hash;
 pub mod accounts_index;
 pub mod ancestors;
 pub mod append_vec;
+pub mod async_accounts_db;
 pub mod bank;
 pub mod bank_client;
 pub mod bank_forks;
@@ -21,13 +22,16 @@ pub mod epoch_stakes;
 pub mod genesis_utils;
 pub mod hardened_unpack;
 pub mod hashed_transaction;
+pub mod hot_accounts;
 pub mod inline_spl_token_v2_0;
 pub mod instruction_recorder;
 pub mod loader_utils;
 pub mod log_collector;
 pub mod message_processor;
+pub mod metrics_sink;
 mod native_loader;
 pub mod non_circulating_supply;
+pub mod overlay_accounts_view;
 mod read_only_accounts_cache;
 pub mod rent_collector;
 pub mod secondary_index;
@@ -36,6 +40,8 @@ pub mod snapshot_package;
 pub mod snapshot_utils;
 pub mod stakes;
 pub mod status_cache;
+pub mod storage_bloom;
+pub mod storage_replication;
 mod system_instruction_processor;
 pub mod transaction_batch;
 pub mod vote_account;
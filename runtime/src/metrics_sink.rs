@@ -0,0 +1,116 @@
+//! Destinations for the datapoints `AccountsDb` reports. The validator submits them to the
+//! metrics agent as before, while embedders such as tests or ledger-tool can capture them or
+//! turn them off.
+
+use log::Level;
+use solana_metrics::datapoint::DataPoint;
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+pub trait MetricsSink: Debug + Send + Sync {
+    /// Whether datapoints of `level` are wanted at all, checked before they're built
+    fn enabled(&self, _level: Level) -> bool {
+        true
+    }
+
+    fn submit(&self, point: DataPoint, level: Level);
+}
+
+/// Submits to the metrics agent, the same as the `datapoint_*!` macros
+#[derive(Debug, Default)]
+pub struct InfluxMetricsSink;
+
+impl MetricsSink for InfluxMetricsSink {
+    fn enabled(&self, level: Level) -> bool {
+        log::log_enabled!(level)
+    }
+
+    fn submit(&self, point: DataPoint, level: Level) {
+        solana_metrics::submit(point, level);
+    }
+}
+
+/// Drops everything
+#[derive(Debug, Default)]
+pub struct NullMetricsSink;
+
+impl MetricsSink for NullMetricsSink {
+    fn enabled(&self, _level: Level) -> bool {
+        false
+    }
+
+    fn submit(&self, _point: DataPoint, _level: Level) {}
+}
+
+/// Keeps every datapoint until it's taken
+#[derive(Debug, Default)]
+pub struct CapturingMetricsSink {
+    points: Mutex<Vec<(DataPoint, Level)>>,
+}
+
+impl CapturingMetricsSink {
+    pub fn take_points(&self) -> Vec<(DataPoint, Level)> {
+        std::mem::take(&mut *self.points.lock().unwrap())
+    }
+}
+
+impl MetricsSink for CapturingMetricsSink {
+    fn submit(&self, point: DataPoint, level: Level) {
+        self.points.lock().unwrap().push((point, level));
+    }
+}
+
+/// Throttles a periodic report that may be triggered from several threads at once
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    last_report: AtomicU64,
+}
+
+impl RateLimiter {
+    /// Whether more than `interval_ms` passed since the last report, in which case the caller
+    /// owns this one. The very first call only starts the clock.
+    pub fn should_report(&self, interval_ms: u64) -> bool {
+        self.try_report(interval_ms) == Some(true)
+    }
+
+    /// Like `should_report()`, but the very first call reports too
+    pub fn should_report_from_start(&self, interval_ms: u64) -> bool {
+        self.try_report(interval_ms).is_some()
+    }
+
+    // Claims the report if it's due, returning whether an earlier one was claimed before
+    fn try_report(&self, interval_ms: u64) -> Option<bool> {
+        let last = self.last_report.load(Ordering::Relaxed);
+        let now = solana_sdk::timing::timestamp();
+        (now.saturating_sub(last) > interval_ms
+            && self
+                .last_report
+                .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+                == Ok(last))
+        .then(|| last != 0)
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::default();
+        assert!(!limiter.should_report(0));
+        // the clock has millisecond resolution
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert!(limiter.should_report(0));
+        assert!(!limiter.should_report(60_000));
+
+        let limiter = RateLimiter::default();
+        assert!(limiter.should_report_from_start(60_000));
+        assert!(!limiter.should_report_from_start(60_000));
+    }
+}
@@ -0,0 +1,153 @@
+//! A writable view of an AccountsDb for transaction simulation and preflight checks. Writes go
+//! to an in-memory map layered over the AccountsDb, and loads find them before anything in the
+//! AccountsDb. Nothing is ever written to the AccountsDb, its cache or its storages, so a view
+//! is created per request and simply dropped afterwards.
+
+use crate::{
+    accounts_db::{AccountsDb, LoadHint},
+    ancestors::AncestorProvider,
+};
+use solana_sdk::{account::AccountSharedData, clock::Slot, pubkey::Pubkey};
+use std::collections::HashMap;
+
+pub struct OverlayAccountsView<'a> {
+    accounts_db: &'a AccountsDb,
+    slot: Slot,
+    writes: HashMap<Pubkey, AccountSharedData>,
+}
+
+impl<'a> OverlayAccountsView<'a> {
+    /// Hypothetical writes are made at `slot`, normally the slot of the bank simulating
+    pub fn new(accounts_db: &'a AccountsDb, slot: Slot) -> Self {
+        Self {
+            accounts_db,
+            slot,
+            writes: HashMap::new(),
+        }
+    }
+
+    pub fn slot(&self) -> Slot {
+        self.slot
+    }
+
+    /// Like `AccountsDb::store_cached()`, but only into the overlay. A later write of the same
+    /// pubkey replaces the earlier one.
+    pub fn store(&mut self, accounts: &[(&Pubkey, &AccountSharedData)]) {
+        for (pubkey, account) in accounts {
+            self.writes.insert(**pubkey, (*account).clone());
+        }
+    }
+
+    /// Like `AccountsDb::load()`. A pubkey written to the overlay is found at the overlay's slot
+    /// whatever `ancestors` are, zero lamports included, as the written version is the newest.
+    pub fn load(
+        &self,
+        ancestors: &dyn AncestorProvider,
+        pubkey: &Pubkey,
+        load_hint: LoadHint,
+    ) -> Option<(AccountSharedData, Slot)> {
+        match self.writes.get(pubkey) {
+            Some(account) => Some((account.clone(), self.slot)),
+            None => self.accounts_db.load(ancestors, pubkey, load_hint),
+        }
+    }
+
+    pub fn load_with_fixed_root(
+        &self,
+        ancestors: &dyn AncestorProvider,
+        pubkey: &Pubkey,
+    ) -> Option<(AccountSharedData, Slot)> {
+        self.load(ancestors, pubkey, LoadHint::FixedMaxRoot)
+    }
+
+    pub fn load_without_fixed_root(
+        &self,
+        ancestors: &dyn AncestorProvider,
+        pubkey: &Pubkey,
+    ) -> Option<(AccountSharedData, Slot)> {
+        self.load(ancestors, pubkey, LoadHint::Unspecified)
+    }
+
+    /// Whether `pubkey` was written to the overlay
+    pub fn is_written(&self, pubkey: &Pubkey) -> bool {
+        self.writes.contains_key(pubkey)
+    }
+
+    /// The accounts written to the overlay, in no particular order
+    pub fn written_accounts(&self) -> impl Iterator<Item = (&Pubkey, &AccountSharedData)> {
+        self.writes.iter()
+    }
+
+    /// Drops the overlay's writes, so the view can be reused for another request
+    pub fn clear(&mut self) {
+        self.writes.clear();
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::ancestors::Ancestors;
+    use solana_sdk::account::ReadableAccount;
+
+    #[test]
+    fn test_overlay_accounts_view() {
+        let db = AccountsDb::new_single();
+        let owner = Pubkey::new_unique();
+        let stored_key = Pubkey::new_unique();
+        let new_key = Pubkey::new_unique();
+        let stored = AccountSharedData::new(1, 0, &owner);
+        db.store_uncached(0, &[(&stored_key, &stored)]);
+        db.add_root(0);
+        let ancestors = Ancestors::from(vec![0]);
+
+        let mut view = OverlayAccountsView::new(&db, 1);
+        assert_eq!(
+            view.load_without_fixed_root(&ancestors, &stored_key),
+            Some((stored.clone(), 0))
+        );
+        assert!(view.load_without_fixed_root(&ancestors, &new_key).is_none());
+
+        let updated = AccountSharedData::new(2, 0, &owner);
+        let created = AccountSharedData::new(3, 0, &owner);
+        view.store(&[(&stored_key, &updated), (&new_key, &created)]);
+        assert_eq!(
+            view.load_without_fixed_root(&ancestors, &stored_key),
+            Some((updated, 1))
+        );
+        assert_eq!(
+            view.load_with_fixed_root(&ancestors, &new_key),
+            Some((created.clone(), 1))
+        );
+        assert!(view.is_written(&new_key));
+        assert_eq!(view.written_accounts().count(), 2);
+
+        // closing an account in the overlay hides the stored version
+        let closed = AccountSharedData::default();
+        view.store(&[(&stored_key, &closed)]);
+        assert_eq!(
+            view.load_without_fixed_root(&ancestors, &stored_key)
+                .unwrap()
+                .0
+                .lamports(),
+            0
+        );
+
+        // the AccountsDb never saw the writes
+        assert_eq!(
+            db.load_without_fixed_root(&ancestors, &stored_key),
+            Some((stored.clone(), 0))
+        );
+        assert!(db
+            .load_without_fixed_root(&Ancestors::from(vec![0, 1]), &new_key)
+            .is_none());
+        assert!(db.accounts_cache.slot_cache(1).is_none());
+
+        view.clear();
+        assert_eq!(
+            view.load_without_fixed_root(&ancestors, &stored_key),
+            Some((stored, 0))
+        );
+        assert!(view.load_without_fixed_root(&ancestors, &new_key).is_none());
+    }
+}
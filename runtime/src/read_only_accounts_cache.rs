@@ -2,14 +2,20 @@
 //! which can be large, loaded many times, and rarely change.
 use dashmap::{mapref::entry::Entry, DashMap};
 //use mapref::entry::{Entry, OccupiedEntry, VacantEntry};
+use serde::{Deserialize, Serialize};
 use solana_sdk::{
-    account::{AccountSharedData, ReadableAccount},
+    account::{Account, AccountSharedData, ReadableAccount},
     clock::Slot,
     pubkey::Pubkey,
 };
 use std::thread::{sleep, Builder, JoinHandle};
 use std::time::Duration;
 use std::{
+    collections::{hash_map::RandomState, HashSet},
+    fs::File,
+    hash::{BuildHasher, Hash, Hasher},
+    io::{self, BufReader, BufWriter, Write},
+    path::Path,
     sync::{
         atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc, RwLock,
@@ -20,6 +26,85 @@ use std::{
 type ReadOnlyCacheKey = (Pubkey, Slot);
 type LruEntry = (Instant, ReadOnlyCacheKey);
 
+/// Most entries `pin()` keeps pinned at once
+pub const MAX_PINNED_ENTRIES: usize = 1024;
+
+/// Version of the file written by `ReadOnlyAccountsCache::save()`
+const SAVED_CACHE_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct SavedCache {
+    version: u32,
+    // least recently used first
+    entries: Vec<(Pubkey, Slot, Account)>,
+}
+
+/// Which of the accounts offered to `ReadOnlyAccountsCache::store()` get cached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadOnlyCacheAdmission {
+    Always,
+    /// Only accounts already offered within about the last `window` first offers of other
+    /// accounts are cached, so a scan loading every account once doesn't evict the accounts
+    /// loaded over and over
+    SeenBefore {
+        window: usize,
+    },
+}
+
+// Approximately remembers the accounts offered since it was last cleared, in a bloom filter
+// of two bits per account, like the doorkeeper of TinyLFU. It's cleared once `window`
+// accounts were newly inserted, so only accounts offered again soon are admitted.
+#[derive(Debug)]
+struct Doorkeeper {
+    bits: Vec<AtomicU64>,
+    window: usize,
+    num_inserted: AtomicUsize,
+    // seeded per process, so pubkeys can't be ground to collide
+    hasher: RandomState,
+}
+
+impl Doorkeeper {
+    // about 5% false positives at a full window
+    const BITS_PER_ACCOUNT: usize = 8;
+
+    fn new(window: usize) -> Self {
+        let num_words = std::cmp::max(16, window * Self::BITS_PER_ACCOUNT / 64);
+        Self {
+            bits: (0..num_words).map(|_| AtomicU64::default()).collect(),
+            window,
+            num_inserted: AtomicUsize::default(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Records `pubkey`, returning whether it was recorded before
+    fn check_and_insert(&self, pubkey: &Pubkey) -> bool {
+        let mut hasher = self.hasher.build_hasher();
+        pubkey.hash(&mut hasher);
+        let hash = hasher.finish();
+        let num_bits = (self.bits.len() * 64) as u64;
+        let mut seen = true;
+        for bit in [
+            (hash >> 32) % num_bits,
+            (hash & u64::from(u32::MAX)) % num_bits,
+        ]
+        .iter()
+        {
+            let mask = 1 << (*bit % 64);
+            let previous = self.bits[(*bit / 64) as usize].fetch_or(mask, Ordering::Relaxed);
+            seen &= previous & mask != 0;
+        }
+        if !seen && self.num_inserted.fetch_add(1, Ordering::Relaxed) + 1 >= self.window {
+            // racing inserts may be lost along with the rest, which only costs them a chance
+            self.num_inserted.store(0, Ordering::Relaxed);
+            for word in &self.bits {
+                word.store(0, Ordering::Relaxed);
+            }
+        }
+        seen
+    }
+}
+
 #[derive(Debug)]
 pub struct ReadOnlyAccountCacheEntry {
     pub account: AccountSharedData,
@@ -29,11 +114,17 @@ pub struct ReadOnlyAccountCacheEntry {
 #[derive(Debug)]
 pub struct ReadOnlyAccountsCache {
     cache: Arc<DashMap<ReadOnlyCacheKey, ReadOnlyAccountCacheEntry>>,
-    max_data_size: usize,
+    max_data_size: Arc<AtomicUsize>,
     data_size: Arc<AtomicUsize>,
     hits: AtomicU64,
     misses: AtomicU64,
+    // accounts `store()` didn't cache, as they weren't admitted
+    rejected: AtomicU64,
     per_account_size: usize,
+    // Entries never purged to make room
+    pinned: Arc<RwLock<HashSet<ReadOnlyCacheKey>>>,
+    // None when every account is admitted
+    doorkeeper: RwLock<Option<Doorkeeper>>,
     stop: Arc<AtomicBool>,
     background: Option<JoinHandle<()>>,
 }
@@ -52,12 +143,16 @@ impl ReadOnlyAccountsCache {
         let mut result = Self::new_test(max_data_size);
 
         let bg = Self {
-            max_data_size,
+            max_data_size: result.max_data_size.clone(),
             cache: result.cache.clone(),
             data_size: result.data_size.clone(),
             hits: AtomicU64::new(0),
             misses: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
             per_account_size: Self::per_account_size(),
+            pinned: result.pinned.clone(),
+            // the background thread never stores
+            doorkeeper: RwLock::default(),
             stop: result.stop.clone(),
             background: None,
         };
@@ -76,12 +171,15 @@ impl ReadOnlyAccountsCache {
 
     fn new_test(max_data_size: usize) -> Self {
         Self {
-            max_data_size,
+            max_data_size: Arc::new(AtomicUsize::new(max_data_size)),
             cache: Arc::new(DashMap::default()),
             data_size: Arc::new(AtomicUsize::new(0)),
             hits: AtomicU64::new(0),
             misses: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
             per_account_size: Self::per_account_size(),
+            pinned: Arc::default(),
+            doorkeeper: RwLock::default(),
             stop: Arc::new(AtomicBool::new(false)),
             background: None,
         }
@@ -113,7 +211,20 @@ impl ReadOnlyAccountsCache {
         account.data().len() + self.per_account_size
     }
 
+    /// Caches `account` if the admission policy admits it, see `set_admission()`. Accounts
+    /// pinned at `slot` are always admitted.
     pub fn store(&self, pubkey: &Pubkey, slot: Slot, account: &AccountSharedData) {
+        if let Some(doorkeeper) = self.doorkeeper.read().unwrap().as_ref() {
+            if !doorkeeper.check_and_insert(pubkey) && !self.is_pinned(pubkey, slot) {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        }
+        self.force_store(pubkey, slot, account);
+    }
+
+    /// Caches `account` whatever the admission policy, e.g. when it's known to be loaded soon
+    pub fn force_store(&self, pubkey: &Pubkey, slot: Slot, account: &AccountSharedData) {
         let len = self.account_size(account);
         let previous_len = if let Some(previous) = self.cache.insert(
             (*pubkey, slot),
@@ -149,17 +260,37 @@ impl ReadOnlyAccountsCache {
         }
     }
 
+    /// Keeps the entry of `pubkey` at `slot` from being purged to make room, until it's
+    /// unpinned. Returns false if `MAX_PINNED_ENTRIES` entries are pinned already.
+    pub fn pin(&self, pubkey: &Pubkey, slot: Slot) -> bool {
+        let mut pinned = self.pinned.write().unwrap();
+        pinned.len() < MAX_PINNED_ENTRIES && pinned.insert((*pubkey, slot))
+    }
+
+    pub fn unpin(&self, pubkey: &Pubkey, slot: Slot) {
+        self.pinned.write().unwrap().remove(&(*pubkey, slot));
+    }
+
+    pub fn is_pinned(&self, pubkey: &Pubkey, slot: Slot) -> bool {
+        self.pinned.read().unwrap().contains(&(*pubkey, slot))
+    }
+
     fn purge_lru_list(&self, lru: &[LruEntry], lru_index: &mut usize) -> bool {
         let mut freed_bytes = 0;
         let start = *lru_index;
         let mut done = false;
         let current_size = self.data_size.load(Ordering::Relaxed);
+        let max_data_size = self.max_data_size.load(Ordering::Relaxed);
+        let pinned = self.pinned.read().unwrap();
         for (timestamp, key) in lru.iter().skip(start) {
-            if current_size.saturating_sub(freed_bytes) <= self.max_data_size {
+            if current_size.saturating_sub(freed_bytes) <= max_data_size {
                 done = true;
                 break;
             }
             *lru_index += 1;
+            if pinned.contains(key) {
+                continue;
+            }
             match self.cache.entry(*key) {
                 Entry::Vacant(_entry) => (),
                 Entry::Occupied(entry) => {
@@ -223,6 +354,29 @@ impl ReadOnlyAccountsCache {
         }
     }
 
+    /// Which accounts `store()` caches from now on. Switching to `SeenBefore` starts with no
+    /// account seen, and accounts already cached stay.
+    pub fn set_admission(&self, admission: ReadOnlyCacheAdmission) {
+        *self.doorkeeper.write().unwrap() = match admission {
+            ReadOnlyCacheAdmission::Always => None,
+            ReadOnlyCacheAdmission::SeenBefore { window } => Some(Doorkeeper::new(window)),
+        };
+    }
+
+    pub fn admission(&self) -> ReadOnlyCacheAdmission {
+        match self.doorkeeper.read().unwrap().as_ref() {
+            None => ReadOnlyCacheAdmission::Always,
+            Some(doorkeeper) => ReadOnlyCacheAdmission::SeenBefore {
+                window: doorkeeper.window,
+            },
+        }
+    }
+
+    /// Entries beyond this many bytes are purged by the background thread
+    pub fn set_max_data_size(&self, max_data_size: usize) {
+        self.max_data_size.store(max_data_size, Ordering::Relaxed);
+    }
+
     pub fn cache_len(&self) -> usize {
         self.cache.len()
     }
@@ -236,12 +390,109 @@ impl ReadOnlyAccountsCache {
         let misses = self.misses.swap(0, Ordering::Relaxed);
         (hits, misses)
     }
+
+    /// Number of accounts `store()` didn't cache since the last call, as they weren't admitted
+    pub fn get_and_reset_rejected(&self) -> u64 {
+        self.rejected.swap(0, Ordering::Relaxed)
+    }
+
+    /// Writes the cached accounts to `path`, for `read_saved()` to read back after a restart.
+    /// Returns the number of accounts written.
+    pub fn save(&self, path: &Path) -> io::Result<usize> {
+        let mut entries: Vec<_> = self
+            .cache
+            .iter()
+            .map(|item| {
+                let (pubkey, slot) = *item.key();
+                let value = item.value();
+                let last_used = *value.last_used.read().unwrap();
+                (
+                    last_used,
+                    pubkey,
+                    slot,
+                    Account::from(value.account.clone()),
+                )
+            })
+            .collect();
+        entries.sort_unstable_by_key(|(last_used, ..)| *last_used);
+        let saved = SavedCache {
+            version: SAVED_CACHE_VERSION,
+            entries: entries
+                .into_iter()
+                .map(|(_last_used, pubkey, slot, account)| (pubkey, slot, account))
+                .collect(),
+        };
+
+        // a partially written file must not be read back
+        let tmp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        bincode::serialize_into(&mut writer, &saved)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        writer.flush()?;
+        drop(writer);
+        std::fs::rename(&tmp_path, path)?;
+        Ok(saved.entries.len())
+    }
+
+    /// Reads the accounts `save()` wrote to `path`, least recently used first, so that
+    /// storing them in order keeps their order of use
+    pub fn read_saved(path: &Path) -> io::Result<Vec<(Pubkey, Slot, AccountSharedData)>> {
+        let reader = BufReader::new(File::open(path)?);
+        let saved: SavedCache = bincode::deserialize_from(reader)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        if saved.version != SAVED_CACHE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "saved read-only accounts cache has version {}, expected {}",
+                    saved.version, SAVED_CACHE_VERSION
+                ),
+            ));
+        }
+        Ok(saved
+            .entries
+            .into_iter()
+            .map(|(pubkey, slot, account)| (pubkey, slot, AccountSharedData::from(account)))
+            .collect())
+    }
 }
 
 #[cfg(test)]
 pub mod tests {
     use super::*;
     use solana_sdk::account::{accounts_equal, Account, WritableAccount};
+    #[test]
+    fn test_save_and_read_saved() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("read_only_accounts_cache");
+        let cache = ReadOnlyAccountsCache::new_test(usize::MAX);
+        let keys: Vec<_> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        for (i, key) in keys.iter().enumerate() {
+            let account = AccountSharedData::new(i as u64 + 1, i, &Pubkey::default());
+            cache.store(key, i as Slot, &account);
+            sleep(Duration::from_millis(1));
+        }
+        // the first becomes the most recently used
+        cache.load(&keys[0], 0);
+
+        assert_eq!(cache.save(&path).unwrap(), 3);
+        let saved = ReadOnlyAccountsCache::read_saved(&path).unwrap();
+        assert_eq!(
+            saved
+                .iter()
+                .map(|(pubkey, slot, account)| (*pubkey, *slot, account.lamports()))
+                .collect::<Vec<_>>(),
+            vec![(keys[1], 1, 2), (keys[2], 2, 3), (keys[0], 0, 1)]
+        );
+        assert_eq!(saved[1].2.data().len(), 2);
+
+        std::fs::write(&path, b"garbage").unwrap();
+        assert_eq!(
+            ReadOnlyAccountsCache::read_saved(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
     #[test]
     fn test_accountsdb_sizeof() {
         // size_of(arc(x)) does not return the size of x
@@ -258,6 +509,106 @@ pub mod tests {
         assert!(stop.load(Ordering::Relaxed));
     }
 
+    #[test]
+    fn test_read_only_accounts_cache_pinned() {
+        let per_account_size = ReadOnlyAccountsCache::per_account_size();
+        let cache = ReadOnlyAccountsCache::new_test(per_account_size);
+        let (key1, key2) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let account = AccountSharedData::default();
+        assert!(cache.pin(&key1, 0));
+        cache.store(&key1, 0, &account);
+        cache.store(&key2, 0, &account);
+        cache.bg_purge_lru_items(true);
+        // the older entry stays since it's pinned
+        assert!(cache.load(&key1, 0).is_some());
+        assert!(cache.load(&key2, 0).is_none());
+
+        cache.unpin(&key1, 0);
+        assert!(!cache.is_pinned(&key1, 0));
+        cache.store(&key2, 0, &account);
+        cache.load(&key2, 0);
+        cache.bg_purge_lru_items(true);
+        assert!(cache.load(&key1, 0).is_none());
+
+        // only the pinned version of an account is kept
+        assert!(cache.pin(&key1, 1));
+        cache.store(&key1, 0, &account);
+        cache.store(&key1, 1, &account);
+        cache.bg_purge_lru_items(true);
+        assert!(cache.load(&key1, 0).is_none());
+        assert!(cache.load(&key1, 1).is_some());
+
+        for slot in 2..=MAX_PINNED_ENTRIES as Slot {
+            assert!(cache.pin(&key2, slot));
+        }
+        assert!(!cache.pin(&key2, 0));
+        assert!(!cache.is_pinned(&key2, 0));
+    }
+
+    #[test]
+    fn test_read_only_accounts_cache_admission() {
+        let per_account_size = ReadOnlyAccountsCache::per_account_size();
+        let cache = ReadOnlyAccountsCache::new_test(per_account_size * 4);
+        assert_eq!(cache.admission(), ReadOnlyCacheAdmission::Always);
+        let admission = ReadOnlyCacheAdmission::SeenBefore { window: 1000 };
+        cache.set_admission(admission);
+        assert_eq!(cache.admission(), admission);
+        let account = AccountSharedData::default();
+        let hot: Vec<_> = (0..2).map(|_| Pubkey::new_unique()).collect();
+
+        // the first offer is only remembered
+        for key in &hot {
+            cache.store(key, 0, &account);
+            assert!(cache.load(key, 0).is_none());
+        }
+        assert_eq!(cache.get_and_reset_rejected(), 2);
+        for key in &hot {
+            cache.store(key, 0, &account);
+            assert!(cache.load(key, 0).is_some());
+        }
+
+        // a scan loading lots of accounts once leaves the hot ones cached
+        let scanned: Vec<_> = (0..100).map(|_| Pubkey::new_unique()).collect();
+        for key in &scanned {
+            cache.store(key, 0, &account);
+        }
+        cache.bg_purge_lru_items(true);
+        for key in &hot {
+            assert!(cache.load(key, 0).is_some());
+        }
+        // there may be a few false positives
+        assert!(cache.get_and_reset_rejected() > 90);
+        assert!(cache.cache_len() < 5);
+
+        // pinned accounts and forced stores skip admission
+        let pinned = Pubkey::new_unique();
+        cache.pin(&pinned, 0);
+        cache.store(&pinned, 0, &account);
+        assert!(cache.load(&pinned, 0).is_some());
+        let forced = Pubkey::new_unique();
+        cache.force_store(&forced, 0, &account);
+        assert!(cache.load(&forced, 0).is_some());
+
+        cache.set_admission(ReadOnlyCacheAdmission::Always);
+        let key = Pubkey::new_unique();
+        cache.store(&key, 0, &account);
+        assert!(cache.load(&key, 0).is_some());
+        assert_eq!(cache.get_and_reset_rejected(), 0);
+    }
+
+    #[test]
+    fn test_doorkeeper_window() {
+        let doorkeeper = Doorkeeper::new(4);
+        let keys: Vec<_> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        for key in &keys {
+            assert!(!doorkeeper.check_and_insert(key));
+        }
+        assert!(doorkeeper.check_and_insert(&keys[0]));
+        // the 4th new account fills the window, which forgets everything seen
+        assert!(!doorkeeper.check_and_insert(&Pubkey::new_unique()));
+        assert!(!doorkeeper.check_and_insert(&keys[0]));
+    }
+
     #[test]
     fn test_read_only_accounts_cache() {
         solana_logger::setup();
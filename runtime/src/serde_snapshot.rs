@@ -1,10 +1,12 @@
 use {
     crate::{
         accounts::Accounts,
-        accounts_db::{AccountStorageEntry, AccountsDb, AppendVecId, BankHashInfo},
+        accounts_db::{
+            AccountStorageEntry, AccountsDb, AppendVecId, BankHashInfo, GenerateIndexReport,
+        },
         accounts_index::AccountSecondaryIndexes,
         ancestors::Ancestors,
-        append_vec::{AppendVec, StoredMetaWriteVersion},
+        append_vec::{AppendVec, AppendVecError, StoredMetaWriteVersion},
         bank::{Bank, BankFieldsToDeserialize, BankRc, Builtins},
         blockhash_queue::BlockhashQueue,
         epoch_stakes::EpochStakes,
@@ -31,8 +33,9 @@ use {
     },
     std::{
         collections::{HashMap, HashSet},
+        fs,
         io::{self, BufReader, BufWriter, Read, Write},
-        path::PathBuf,
+        path::{Path, PathBuf},
         result::Result,
         sync::{atomic::Ordering, Arc, RwLock},
         time::Instant,
@@ -123,6 +126,21 @@ where
         .deserialize_from::<R, T>(reader)
 }
 
+/// Reads a field appended to the stream after those older versions wrote, defaulting it for
+/// streams written without it
+fn deserialize_trailing_from<R, T>(reader: R) -> bincode::Result<T>
+where
+    R: Read,
+    T: DeserializeOwned + Default,
+{
+    match deserialize_from(reader) {
+        Err(err) if matches!(*err, bincode::ErrorKind::Io(ref io_err) if io_err.kind() == io::ErrorKind::UnexpectedEof) => {
+            Ok(T::default())
+        }
+        result => result,
+    }
+}
+
 #[allow(clippy::too_many_arguments)]
 pub(crate) fn bank_from_stream<R>(
     serde_style: SerdeStyle,
@@ -136,15 +154,17 @@ pub(crate) fn bank_from_stream<R>(
     account_indexes: AccountSecondaryIndexes,
     caching_enabled: bool,
     limit_load_slot_count_from_snapshot: Option<usize>,
-) -> std::result::Result<Bank, Error>
+    verify_storage_hashes: bool,
+) -> std::result::Result<(Bank, GenerateIndexReport), Error>
 where
     R: Read,
 {
     macro_rules! INTO {
         ($x:ident) => {{
             let (bank_fields, accounts_db_fields) = $x::deserialize_bank_fields(stream)?;
+            let created_at_slots: Vec<(Pubkey, Slot)> = deserialize_trailing_from(&mut *stream)?;
 
-            let bank = reconstruct_bank_from_fields(
+            let (bank, generate_index_report) = reconstruct_bank_from_fields(
                 bank_fields,
                 accounts_db_fields,
                 genesis_config,
@@ -156,8 +176,14 @@ where
                 account_indexes,
                 caching_enabled,
                 limit_load_slot_count_from_snapshot,
+                verify_storage_hashes,
             )?;
-            Ok(bank)
+            bank.rc
+                .accounts
+                .accounts_db
+                .accounts_index
+                .restore_created_at_slots(&created_at_slots);
+            Ok((bank, generate_index_report))
         }};
     }
     match serde_style {
@@ -181,13 +207,18 @@ where
     macro_rules! INTO {
         ($x:ident) => {
             bincode::serialize_into(
-                stream,
+                &mut *stream,
                 &SerializableBankAndStorage::<$x> {
                     bank,
                     snapshot_storages,
                     phantom: std::marker::PhantomData::default(),
                 },
             )
+            // the creation slots whose versions were reclaimed aren't in the storages
+            .and_then(|_| {
+                let accounts_index = &bank.rc.accounts.accounts_db.accounts_index;
+                bincode::serialize_into(&mut *stream, &accounts_index.created_at_slots(bank.slot()))
+            })
         };
     }
     match serde_style {
@@ -246,11 +277,12 @@ fn reconstruct_bank_from_fields<E>(
     account_indexes: AccountSecondaryIndexes,
     caching_enabled: bool,
     limit_load_slot_count_from_snapshot: Option<usize>,
-) -> Result<Bank, Error>
+    verify_storage_hashes: bool,
+) -> Result<(Bank, GenerateIndexReport), Error>
 where
     E: SerializableStorage,
 {
-    let mut accounts_db = reconstruct_accountsdb_from_fields(
+    let (mut accounts_db, generate_index_report) = reconstruct_accountsdb_from_fields(
         accounts_db_fields,
         account_paths,
         unpacked_append_vec_map,
@@ -258,11 +290,14 @@ where
         account_indexes,
         caching_enabled,
         limit_load_slot_count_from_snapshot,
+        verify_storage_hashes,
     )?;
-    accounts_db.freeze_accounts(
-        &Ancestors::from(&bank_fields.ancestors),
-        frozen_account_pubkeys,
-    );
+    accounts_db
+        .freeze_accounts(
+            &Ancestors::from(&bank_fields.ancestors),
+            frozen_account_pubkeys,
+        )
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
 
     let bank_rc = BankRc::new(Accounts::new_empty(accounts_db), bank_fields.slot);
     let bank = Bank::new_from_fields(
@@ -273,9 +308,23 @@ where
         additional_builtins,
     );
 
-    Ok(bank)
+    Ok((bank, generate_index_report))
 }
 
+// Moves a storage file that failed validation into a `quarantine` directory next to it,
+// where it is kept for inspection rather than being cleaned up with the unpacked snapshot
+fn quarantine_storage_file(path: &Path) -> io::Result<PathBuf> {
+    let quarantine_dir = path
+        .parent()
+        .map(|dir| dir.join("quarantine"))
+        .unwrap_or_else(|| PathBuf::from("quarantine"));
+    fs::create_dir_all(&quarantine_dir)?;
+    let quarantined_path = quarantine_dir.join(path.file_name().unwrap_or_default());
+    fs::rename(path, &quarantined_path)?;
+    Ok(quarantined_path)
+}
+
+// Also returns the report of the index generation over the deserialized storages
 fn reconstruct_accountsdb_from_fields<E>(
     accounts_db_fields: AccountsDbFields<E>,
     account_paths: &[PathBuf],
@@ -284,7 +333,8 @@ fn reconstruct_accountsdb_from_fields<E>(
     account_indexes: AccountSecondaryIndexes,
     caching_enabled: bool,
     limit_load_slot_count_from_snapshot: Option<usize>,
-) -> Result<AccountsDb, Error>
+    verify_storage_hashes: bool,
+) -> Result<(AccountsDb, GenerateIndexReport), Error>
 where
     E: SerializableStorage,
 {
@@ -293,17 +343,13 @@ where
         cluster_type,
         account_indexes,
         caching_enabled,
-    );
+    )
+    .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
     let AccountsDbFields(storage, version, slot, bank_hash_info) = accounts_db_fields;
 
-    // Ensure all account paths exist
-    for path in &accounts_db.paths {
-        std::fs::create_dir_all(path)
-            .unwrap_or_else(|err| panic!("Failed to create directory {}: {}", path.display(), err));
-    }
-
     let mut last_log_update = Instant::now();
     let mut remaining_slots_to_process = storage.len();
+    let mut invalid_storage_files = vec![];
 
     // Remap the deserialized AppendVec paths to point to correct local paths
     let mut storage = storage
@@ -327,31 +373,68 @@ where
                     )
                 })?;
 
-                let (accounts, num_accounts) =
-                    AppendVec::new_from_file(append_vec_path, storage_entry.current_len())?;
-                let u_storage_entry = AccountStorageEntry::new_existing(
+                let file_size = fs::metadata(append_vec_path)?.len();
+                let validated = if file_size == 0 {
+                    // an empty file can't even be mapped
+                    Err(AppendVecError::InvalidFileSize {
+                        current_len: storage_entry.current_len(),
+                        file_size,
+                    })
+                } else {
+                    let accounts = AppendVec::new_from_file_unchecked(
+                        append_vec_path,
+                        storage_entry.current_len(),
+                    )?;
+                    match AccountsDb::validate_storage_file(slot, &accounts, verify_storage_hashes)
+                    {
+                        Ok(num_accounts) => Ok((accounts, num_accounts)),
+                        Err(err) => {
+                            accounts.set_no_remove_on_drop();
+                            Err(err)
+                        }
+                    }
+                };
+                let (accounts, num_accounts) = match validated {
+                    Ok(validated) => validated,
+                    Err(err) => {
+                        let quarantined_path = quarantine_storage_file(append_vec_path)?;
+                        warn!(
+                            "invalid storage file {} quarantined at {}: {:?}",
+                            file_name,
+                            quarantined_path.display(),
+                            err
+                        );
+                        invalid_storage_files.push((file_name, err));
+                        continue;
+                    }
+                };
+                let mut u_storage_entry = AccountStorageEntry::new_existing(
                     slot,
                     storage_entry.id(),
                     accounts,
                     num_accounts,
                 );
+                u_storage_entry.charge_usage_to(&accounts_db.storage_usage_bytes);
 
                 new_slot_storage.insert(storage_entry.id(), Arc::new(u_storage_entry));
             }
             Ok((slot, new_slot_storage))
         })
         .collect::<Result<HashMap<Slot, _>, Error>>()?;
+    if !invalid_storage_files.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid storage files: {:?}", invalid_storage_files),
+        )
+        .into());
+    }
 
     // discard any slots with no storage entries
     // this can happen if a non-root slot was serialized
     // but non-root stores should not be included in the snapshot
     storage.retain(|_slot, stores| !stores.is_empty());
 
-    accounts_db
-        .bank_hashes
-        .write()
-        .unwrap()
-        .insert(slot, bank_hash_info);
+    accounts_db.bank_hashes.insert(slot, bank_hash_info);
 
     // Process deserialized data, set necessary fields in self
     let max_id: usize = *storage
@@ -376,6 +459,6 @@ where
     accounts_db
         .write_version
         .fetch_add(version, Ordering::Relaxed);
-    accounts_db.generate_index(limit_load_slot_count_from_snapshot);
-    Ok(accounts_db)
+    let generate_index_report = accounts_db.generate_index(limit_load_slot_count_from_snapshot);
+    Ok((accounts_db, generate_index_report))
 }
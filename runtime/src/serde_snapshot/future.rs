@@ -236,6 +236,20 @@ impl<'a> TypeContext<'a> for Context {
     where
         Self: std::marker::Sized,
     {
+        // versions without support for references to identical data can't read such storages
+        if let Some(store) = serializable_db
+            .account_storage_entries
+            .iter()
+            .flatten()
+            .find(|store| store.accounts.declares_data_refs())
+        {
+            return Err(serde::ser::Error::custom(format!(
+                "storage {} of slot {} holds data references, see AccountsDb::expand_data_refs()",
+                store.append_vec_id(),
+                store.slot()
+            )));
+        }
+
         // sample write version before serializing storage entries
         let version = serializable_db
             .accounts_db
@@ -258,12 +272,8 @@ impl<'a> TypeContext<'a> for Context {
         let slot = serializable_db.slot;
         let hash = serializable_db
             .accounts_db
-            .bank_hashes
-            .read()
-            .unwrap()
-            .get(&serializable_db.slot)
-            .unwrap_or_else(|| panic!("No bank_hashes entry for slot {}", serializable_db.slot))
-            .clone();
+            .bank_hash_info(serializable_db.slot)
+            .unwrap_or_else(|| panic!("No bank_hashes entry for slot {}", serializable_db.slot));
 
         let mut serialize_account_storage_timer = Measure::start("serialize_account_storage_ms");
         let result = (entries, version, slot, hash).serialize(serializer);
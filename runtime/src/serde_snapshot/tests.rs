@@ -74,7 +74,9 @@ where
         AccountSecondaryIndexes::default(),
         false,
         None,
+        false,
     )
+    .map(|(accounts_db, _generate_index_report)| accounts_db)
 }
 
 #[cfg(test)]
@@ -193,6 +195,13 @@ fn test_bank_serialize_style(serde_style: SerdeStyle) {
     bank2.freeze();
     bank2.squash();
     bank2.force_flush_accounts_cache();
+    // as if clean reclaimed the version key2 was created with
+    bank2
+        .rc
+        .accounts
+        .accounts_db
+        .accounts_index
+        .restore_created_at_slots(&[(key2.pubkey(), 1)]);
 
     let snapshot_storages = bank2.get_snapshot_storages();
     let mut buf = vec![];
@@ -216,7 +225,7 @@ fn test_bank_serialize_style(serde_style: SerdeStyle) {
     let copied_accounts = TempDir::new().unwrap();
     let unpacked_append_vec_map =
         copy_append_vecs(&bank2.rc.accounts.accounts_db, copied_accounts.path()).unwrap();
-    let mut dbank = crate::serde_snapshot::bank_from_stream(
+    let (mut dbank, generate_index_report) = crate::serde_snapshot::bank_from_stream(
         serde_style,
         &mut reader,
         &dbank_paths,
@@ -228,12 +237,28 @@ fn test_bank_serialize_style(serde_style: SerdeStyle) {
         AccountSecondaryIndexes::default(),
         false,
         None,
+        true,
     )
     .unwrap();
     dbank.src = ref_sc;
     assert_eq!(dbank.get_balance(&key1.pubkey()), 0);
     assert_eq!(dbank.get_balance(&key2.pubkey()), 10);
     assert_eq!(dbank.get_balance(&key3.pubkey()), 0);
+    assert_eq!(
+        dbank
+            .rc
+            .accounts
+            .accounts_db
+            .account_created_at(&key2.pubkey()),
+        Some(1)
+    );
+    // the report of indexing the snapshot's storages comes along, and a copy stays with the db
+    assert_eq!(generate_index_report.num_slots(), snapshot_storages.len());
+    assert!(generate_index_report.is_healthy());
+    assert_eq!(
+        dbank.rc.accounts.accounts_db.take_generate_index_report(),
+        Some(generate_index_report)
+    );
     assert!(bank2 == dbank);
 }
 
@@ -274,11 +299,149 @@ pub(crate) fn reconstruct_accounts_db_via_serialization(
     accounts_db
 }
 
+#[test]
+fn test_accounts_deserialize_quarantines_invalid_storage() {
+    solana_logger::setup();
+    let accounts = AccountsDb::new_single();
+    let pubkey = solana_sdk::pubkey::new_rand();
+    accounts.store_uncached(0, &[(&pubkey, &AccountSharedData::new(1, 0, &pubkey))]);
+    accounts.add_root(0);
+
+    let mut writer = Cursor::new(vec![]);
+    accountsdb_to_stream(
+        SerdeStyle::Newer,
+        &mut writer,
+        &accounts,
+        0,
+        &accounts.get_snapshot_storages(0),
+    )
+    .unwrap();
+    let copied_accounts = TempDir::new().unwrap();
+    let unpacked_append_vec_map = copy_append_vecs(&accounts, copied_accounts.path()).unwrap();
+
+    // cut the file short of the accounts the snapshot claims it holds
+    let (file_name, path) = unpacked_append_vec_map.iter().next().unwrap();
+    let file_name = file_name.clone();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(path)
+        .unwrap()
+        .set_len(8)
+        .unwrap();
+
+    let buf = writer.into_inner();
+    let mut reader = BufReader::new(&buf[..]);
+    let err = accountsdb_from_stream(SerdeStyle::Newer, &mut reader, &[], unpacked_append_vec_map)
+        .unwrap_err();
+    assert!(err.to_string().contains("InvalidFileSize"));
+    assert!(copied_accounts
+        .path()
+        .join("quarantine")
+        .join(&file_name)
+        .exists());
+}
+
+#[test]
+fn test_accounts_deserialize_quarantines_empty_storage() {
+    let accounts = AccountsDb::new_single();
+    let pubkey = solana_sdk::pubkey::new_rand();
+    accounts.store_uncached(0, &[(&pubkey, &AccountSharedData::new(1, 0, &pubkey))]);
+    accounts.add_root(0);
+
+    let mut writer = Cursor::new(vec![]);
+    accountsdb_to_stream(
+        SerdeStyle::Newer,
+        &mut writer,
+        &accounts,
+        0,
+        &accounts.get_snapshot_storages(0),
+    )
+    .unwrap();
+    let copied_accounts = TempDir::new().unwrap();
+    let unpacked_append_vec_map = copy_append_vecs(&accounts, copied_accounts.path()).unwrap();
+
+    let (file_name, path) = unpacked_append_vec_map.iter().next().unwrap();
+    let file_name = file_name.clone();
+    std::fs::File::create(path).unwrap();
+
+    let buf = writer.into_inner();
+    let mut reader = BufReader::new(&buf[..]);
+    let err = accountsdb_from_stream(SerdeStyle::Newer, &mut reader, &[], unpacked_append_vec_map)
+        .unwrap_err();
+    assert!(err.to_string().contains("InvalidFileSize"));
+    assert!(copied_accounts
+        .path()
+        .join("quarantine")
+        .join(&file_name)
+        .exists());
+}
+
 #[test]
 fn test_accounts_serialize_newer() {
     test_accounts_serialize_style(SerdeStyle::Newer)
 }
 
+#[test]
+fn test_accounts_serialize_deduped_slot() {
+    solana_logger::setup();
+    let (_accounts_dir, paths) = get_temp_accounts_paths(1).unwrap();
+    let accounts_db = AccountsDb::new_with_config(
+        paths,
+        &ClusterType::Development,
+        AccountSecondaryIndexes::default(),
+        true,
+    )
+    .unwrap();
+    accounts_db.set_dedup_account_data(true);
+    let account = AccountSharedData::new(1, 165, &Pubkey::default());
+    let pubkeys: Vec<_> = (0..10).map(|_| Pubkey::new_unique()).collect();
+    let accounts: Vec<_> = pubkeys.iter().map(|pubkey| (pubkey, &account)).collect();
+    accounts_db.store_cached(0, &accounts);
+    accounts_db.get_accounts_delta_hash(0);
+    accounts_db.add_root(0);
+    accounts_db.flush_accounts_cache(true, None);
+    assert!(accounts_db.dedup_stats().0 > 0);
+
+    let serialize = |accounts_db: &AccountsDb| {
+        let mut writer = Cursor::new(vec![]);
+        accountsdb_to_stream(
+            SerdeStyle::Newer,
+            &mut writer,
+            accounts_db,
+            0,
+            &accounts_db.get_snapshot_storages(0),
+        )
+        .map(|_| writer.into_inner())
+    };
+    // versions without support for data references couldn't load the storages
+    assert!(serialize(&accounts_db).is_err());
+
+    assert_eq!(accounts_db.expand_data_refs(0), 1);
+    let buf = serialize(&accounts_db).unwrap();
+    let copied_accounts = TempDir::new().unwrap();
+    let unpacked_append_vec_map = copy_append_vecs(&accounts_db, copied_accounts.path()).unwrap();
+    let (_daccounts_dir, daccounts_paths) = get_temp_accounts_paths(1).unwrap();
+    let daccounts_db = accountsdb_from_stream(
+        SerdeStyle::Newer,
+        &mut BufReader::new(&buf[..]),
+        &daccounts_paths,
+        unpacked_append_vec_map,
+    )
+    .unwrap();
+    assert!(daccounts_db
+        .get_snapshot_storages(0)
+        .iter()
+        .flatten()
+        .all(|store| !store.accounts.declares_data_refs()));
+    let ancestors = Ancestors::default();
+    for pubkey in &pubkeys {
+        assert_eq!(
+            daccounts_db.load_without_fixed_root(&ancestors, pubkey),
+            Some((account.clone(), 0))
+        );
+    }
+}
+
 #[test]
 fn test_bank_serialize_newer() {
     test_bank_serialize_style(SerdeStyle::Newer)
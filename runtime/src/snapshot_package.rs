@@ -1,6 +1,9 @@
 use crate::bank_forks::ArchiveFormat;
 use crate::snapshot_utils::SnapshotVersion;
-use crate::{accounts_db::SnapshotStorages, bank::BankSlotDelta};
+use crate::{
+    accounts_db::{SnapshotStorages, SnapshotWindow},
+    bank::BankSlotDelta,
+};
 use solana_sdk::clock::Slot;
 use solana_sdk::genesis_config::ClusterType;
 use solana_sdk::hash::Hash;
@@ -28,6 +31,8 @@ pub struct AccountsPackagePre {
     pub expected_capitalization: u64,
     pub hash_for_testing: Option<Hash>,
     pub cluster_type: ClusterType,
+    /// Keeps shrink and recycling off `storages` until the package is archived
+    pub snapshot_window: Option<SnapshotWindow>,
 }
 
 impl AccountsPackagePre {
@@ -59,6 +64,7 @@ impl AccountsPackagePre {
             expected_capitalization,
             hash_for_testing,
             cluster_type,
+            snapshot_window: None,
         }
     }
 }
@@ -73,6 +79,8 @@ pub struct AccountsPackage {
     pub hash: Hash,
     pub archive_format: ArchiveFormat,
     pub snapshot_version: SnapshotVersion,
+    /// Keeps shrink and recycling off `storages` until the package is archived
+    pub snapshot_window: Option<SnapshotWindow>,
 }
 
 impl AccountsPackage {
@@ -97,6 +105,7 @@ impl AccountsPackage {
             hash,
             archive_format,
             snapshot_version,
+            snapshot_window: None,
         }
     }
 }
@@ -1,6 +1,6 @@
 use {
     crate::{
-        accounts_db::AccountsDb,
+        accounts_db::{AccountsDb, GenerateIndexReport},
         accounts_index::AccountSecondaryIndexes,
         bank::{Bank, BankSlotDelta, Builtins},
         bank_forks::ArchiveFormat,
@@ -16,14 +16,22 @@ use {
     bzip2::bufread::BzDecoder,
     flate2::read::GzDecoder,
     log::*,
-    rayon::ThreadPool,
+    rand::{thread_rng, Rng},
+    rayon::{prelude::*, ThreadPool},
     regex::Regex,
+    serde::{Deserialize, Serialize},
     solana_measure::measure::Measure,
-    solana_sdk::{clock::Slot, genesis_config::GenesisConfig, hash::Hash, pubkey::Pubkey},
+    solana_sdk::{
+        clock::Slot,
+        genesis_config::GenesisConfig,
+        hash::{Hash, Hasher},
+        pubkey::Pubkey,
+        signature::{Signature, Signer},
+    },
     std::{
         cmp::max,
         cmp::Ordering,
-        collections::HashSet,
+        collections::{HashMap, HashSet},
         fmt,
         fs::{self, File},
         io::{
@@ -127,9 +135,140 @@ pub enum SnapshotError {
 
     #[error("accounts package send error")]
     AccountsPackageSendError(#[from] AccountsPackageSendError),
+
+    #[error("storage manifest verification failed: {0}")]
+    StorageManifest(#[from] StorageManifestError),
 }
 pub type Result<T> = std::result::Result<T, SnapshotError>;
 
+#[derive(Error, Debug, PartialEq)]
+pub enum StorageManifestError {
+    #[error("storage file {0} is not in the manifest")]
+    NotInManifest(String),
+
+    #[error("file {0} of the manifest is not in the snapshot")]
+    MissingFile(String),
+
+    #[error("storage file {file_name} has digest {actual}, expected {expected}")]
+    DigestMismatch {
+        file_name: String,
+        expected: Hash,
+        actual: Hash,
+    },
+
+    #[error("file {0} lacks a valid signature by the trusted signer")]
+    InvalidSignature(String),
+}
+
+/// The expected digest of every storage file in a snapshot archive, and of its bank snapshot
+/// file, see `bank_file_name()`, obtained from a source other than the archive, e.g. when the
+/// archive comes from a mirror. Digests are the sha256 of the whole file. Every entry comes
+/// with its detached signature of the file name and digest, checked against a signer the
+/// caller trusts, so the manifest itself may come from anywhere.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StorageManifest {
+    pub entries: HashMap<String, StorageManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StorageManifestEntry {
+    pub digest: Hash,
+    pub signature: Signature,
+}
+
+impl StorageManifest {
+    /// The name the bank snapshot file of `slot` is recorded under, its path in the archive
+    pub fn bank_file_name(slot: Slot) -> String {
+        format!("snapshots/{0}/{0}", slot)
+    }
+
+    /// Records the digest of the file at `path`, signed by `signer`
+    pub fn add_file(&mut self, file_name: String, path: &Path, signer: &dyn Signer) -> Result<()> {
+        let digest = storage_file_digest(path)?;
+        let signature = signer.sign_message(&Self::signed_message(&file_name, &digest));
+        self.entries
+            .insert(file_name, StorageManifestEntry { digest, signature });
+        Ok(())
+    }
+
+    // Binds the digest to the file name, so a signed entry can't vouch for another file
+    fn signed_message(file_name: &str, digest: &Hash) -> Vec<u8> {
+        bincode::serialize(&(file_name, digest)).unwrap()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        serialize_snapshot_data_file(path, |stream| {
+            serialize_into(stream, self)?;
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        deserialize_snapshot_data_file(path, |stream| {
+            Ok(bincode::options()
+                .with_limit(MAX_SNAPSHOT_DATA_FILE_SIZE)
+                .with_fixint_encoding()
+                .allow_trailing_bytes()
+                .deserialize_from(stream)?)
+        })
+    }
+
+    /// Checks the files unpacked from a snapshot archive, by name, against the manifest. Every
+    /// file must be in the manifest, and every file of the manifest must be there, with a
+    /// valid signature by `trusted_signer`.
+    pub fn verify(
+        &self,
+        trusted_signer: &Pubkey,
+        unpacked_files: &UnpackedAppendVecMap,
+    ) -> Result<()> {
+        if let Some(file_name) = self
+            .entries
+            .keys()
+            .find(|file_name| !unpacked_files.contains_key(*file_name))
+        {
+            return Err(StorageManifestError::MissingFile(file_name.clone()).into());
+        }
+        unpacked_files.par_iter().try_for_each(|(file_name, path)| {
+            let entry = self
+                .entries
+                .get(file_name)
+                .ok_or_else(|| StorageManifestError::NotInManifest(file_name.clone()))?;
+            // checked first, so an unsigned entry can't make us hash files for nothing
+            if !entry.signature.verify(
+                trusted_signer.as_ref(),
+                &Self::signed_message(file_name, &entry.digest),
+            ) {
+                return Err(StorageManifestError::InvalidSignature(file_name.clone()).into());
+            }
+            let digest = storage_file_digest(path)?;
+            if digest != entry.digest {
+                return Err(StorageManifestError::DigestMismatch {
+                    file_name: file_name.clone(),
+                    expected: entry.digest,
+                    actual: digest,
+                }
+                .into());
+            }
+            Ok(())
+        })
+    }
+}
+
+/// The digest a `StorageManifest` records for the storage file at `path`
+pub fn storage_file_digest(path: &Path) -> Result<Hash> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut hasher = Hasher::default();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hasher.result());
+        }
+        hasher.hash(&buffer[..read]);
+    }
+}
+
 impl PartialOrd for SlotSnapshotPaths {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.slot.cmp(&other.slot))
@@ -591,6 +730,8 @@ pub fn remove_snapshot<P: AsRef<Path>>(slot: Slot, snapshot_path: P) -> Result<(
     Ok(())
 }
 
+/// Rebuild a bank from a snapshot archive. Along with the bank comes the report of the index
+/// generation over the snapshot's storages, for the caller to act on suspicious ones.
 #[allow(clippy::too_many_arguments)]
 pub fn bank_from_archive<P: AsRef<Path>>(
     account_paths: &[PathBuf],
@@ -604,7 +745,9 @@ pub fn bank_from_archive<P: AsRef<Path>>(
     account_indexes: AccountSecondaryIndexes,
     accounts_db_caching_enabled: bool,
     limit_load_slot_count_from_snapshot: Option<usize>,
-) -> Result<Bank> {
+    storage_manifest: Option<(&StorageManifest, &Pubkey)>,
+    verify_storage_hashes: bool,
+) -> Result<(Bank, GenerateIndexReport)> {
     let unpack_dir = tempfile::Builder::new()
         .prefix(TMP_SNAPSHOT_PREFIX)
         .tempdir_in(snapshot_path)?;
@@ -615,15 +758,28 @@ pub fn bank_from_archive<P: AsRef<Path>>(
         account_paths,
         archive_format,
     )?;
+    let unpacked_snapshots_dir = unpack_dir.as_ref().join("snapshots");
+    if let Some((storage_manifest, trusted_signer)) = storage_manifest {
+        let mut verify_time = Measure::start("verify storage manifest");
+        let mut unpacked_files = unpacked_append_vec_map.clone();
+        for snapshot_paths in get_snapshot_paths(&unpacked_snapshots_dir) {
+            unpacked_files.insert(
+                StorageManifest::bank_file_name(snapshot_paths.slot),
+                snapshot_paths.snapshot_file_path,
+            );
+        }
+        storage_manifest.verify(trusted_signer, &unpacked_files)?;
+        verify_time.stop();
+        info!("{}", verify_time);
+    }
 
     let mut measure = Measure::start("bank rebuild from snapshot");
-    let unpacked_snapshots_dir = unpack_dir.as_ref().join("snapshots");
     let unpacked_version_file = unpack_dir.as_ref().join("version");
 
     let mut snapshot_version = String::new();
     File::open(unpacked_version_file).and_then(|mut f| f.read_to_string(&mut snapshot_version))?;
 
-    let bank = rebuild_bank_from_snapshots(
+    let (bank, generate_index_report) = rebuild_bank_from_snapshots(
         snapshot_version.trim(),
         frozen_account_pubkeys,
         &unpacked_snapshots_dir,
@@ -635,7 +791,68 @@ pub fn bank_from_archive<P: AsRef<Path>>(
         account_indexes,
         accounts_db_caching_enabled,
         limit_load_slot_count_from_snapshot,
+        verify_storage_hashes,
+    )?;
+
+    if !bank.verify_snapshot_bank() {
+        panic!("Snapshot bank for slot {} failed to verify", bank.slot());
+    }
+    measure.stop();
+    info!("{}", measure);
+
+    Ok((bank, generate_index_report))
+}
+
+/// Rebuild a bank from a snapshot archive that was already unpacked into
+/// `unpacked_snapshot_dir`. The AppendVecs are hardlinked into `account_paths` rather than
+/// copied, which needs no extra disk space, falling back to a copy where linking fails, such
+/// as across filesystems. `unpacked_snapshot_dir` is left intact for later restores. Like
+/// `bank_from_archive()`, it also returns the report of the index generation.
+#[allow(clippy::too_many_arguments)]
+pub fn bank_from_unpacked_snapshot_dir(
+    account_paths: &[PathBuf],
+    frozen_account_pubkeys: &[Pubkey],
+    unpacked_snapshot_dir: &Path,
+    genesis_config: &GenesisConfig,
+    debug_keys: Option<Arc<HashSet<Pubkey>>>,
+    additional_builtins: Option<&Builtins>,
+    account_indexes: AccountSecondaryIndexes,
+    accounts_db_caching_enabled: bool,
+    limit_load_slot_count_from_snapshot: Option<usize>,
+    verify_storage_hashes: bool,
+) -> Result<(Bank, GenerateIndexReport)> {
+    let mut measure = Measure::start("bank rebuild from unpacked snapshot");
+    let (unpacked_append_vec_map, linked_append_vecs) =
+        link_or_copy_append_vecs(&unpacked_snapshot_dir.join("accounts"), account_paths)?;
+    info!(
+        "Linked {} of {} AppendVecs from {}",
+        linked_append_vecs.len(),
+        unpacked_append_vec_map.len(),
+        unpacked_snapshot_dir.display()
+    );
+
+    let mut snapshot_version = String::new();
+    File::open(unpacked_snapshot_dir.join("version"))
+        .and_then(|mut f| f.read_to_string(&mut snapshot_version))?;
+
+    let (bank, generate_index_report) = rebuild_bank_from_snapshots(
+        snapshot_version.trim(),
+        frozen_account_pubkeys,
+        &unpacked_snapshot_dir.join("snapshots"),
+        account_paths,
+        unpacked_append_vec_map,
+        genesis_config,
+        debug_keys,
+        additional_builtins,
+        account_indexes,
+        accounts_db_caching_enabled,
+        limit_load_slot_count_from_snapshot,
+        verify_storage_hashes,
     )?;
+    bank.rc
+        .accounts
+        .accounts_db
+        .mark_storages_linked(&linked_append_vecs);
 
     if !bank.verify_snapshot_bank() {
         panic!("Snapshot bank for slot {} failed to verify", bank.slot());
@@ -643,7 +860,44 @@ pub fn bank_from_archive<P: AsRef<Path>>(
     measure.stop();
     info!("{}", measure);
 
-    Ok(bank)
+    Ok((bank, generate_index_report))
+}
+
+// Place every AppendVec in `unpacked_accounts_dir` in one of `account_paths`, the same way
+// unpacking an archive does, returning where each one went and which ones are hardlinks
+fn link_or_copy_append_vecs(
+    unpacked_accounts_dir: &Path,
+    account_paths: &[PathBuf],
+) -> Result<(UnpackedAppendVecMap, HashSet<String>)> {
+    assert!(!account_paths.is_empty());
+    let mut unpacked_append_vec_map = UnpackedAppendVecMap::new();
+    let mut linked_append_vecs = HashSet::new();
+    for entry in fs::read_dir(unpacked_accounts_dir)? {
+        let entry = entry?;
+        let file_name = match entry.file_name().into_string() {
+            Ok(file_name) => file_name,
+            Err(_) => continue,
+        };
+        let accounts_dir =
+            account_paths[thread_rng().gen_range(0, account_paths.len())].join("accounts");
+        fs::create_dir_all(&accounts_dir)?;
+        let target = accounts_dir.join(&file_name);
+        match fs::hard_link(entry.path(), &target) {
+            Ok(()) => {
+                linked_append_vecs.insert(file_name.clone());
+            }
+            Err(err) => {
+                debug!(
+                    "Copying {} since it can't be linked: {}",
+                    entry.path().display(),
+                    err
+                );
+                fs::copy(entry.path(), &target)?;
+            }
+        }
+        unpacked_append_vec_map.insert(file_name, target);
+    }
+    Ok((unpacked_append_vec_map, linked_append_vecs))
 }
 
 pub fn get_snapshot_archive_path(
@@ -795,7 +1049,8 @@ fn rebuild_bank_from_snapshots(
     account_indexes: AccountSecondaryIndexes,
     accounts_db_caching_enabled: bool,
     limit_load_slot_count_from_snapshot: Option<usize>,
-) -> Result<Bank> {
+    verify_storage_hashes: bool,
+) -> Result<(Bank, GenerateIndexReport)> {
     info!("snapshot version: {}", snapshot_version);
 
     let snapshot_version_enum =
@@ -817,23 +1072,25 @@ fn rebuild_bank_from_snapshots(
         "Loading bank from {}",
         &root_paths.snapshot_file_path.display()
     );
-    let bank = deserialize_snapshot_data_file(&root_paths.snapshot_file_path, |mut stream| {
-        Ok(match snapshot_version_enum {
-            SnapshotVersion::V1_2_0 => bank_from_stream(
-                SerdeStyle::Newer,
-                &mut stream,
-                account_paths,
-                unpacked_append_vec_map,
-                genesis_config,
-                frozen_account_pubkeys,
-                debug_keys,
-                additional_builtins,
-                account_indexes,
-                accounts_db_caching_enabled,
-                limit_load_slot_count_from_snapshot,
-            ),
-        }?)
-    })?;
+    let (bank, generate_index_report) =
+        deserialize_snapshot_data_file(&root_paths.snapshot_file_path, |mut stream| {
+            Ok(match snapshot_version_enum {
+                SnapshotVersion::V1_2_0 => bank_from_stream(
+                    SerdeStyle::Newer,
+                    &mut stream,
+                    account_paths,
+                    unpacked_append_vec_map,
+                    genesis_config,
+                    frozen_account_pubkeys,
+                    debug_keys,
+                    additional_builtins,
+                    account_indexes,
+                    accounts_db_caching_enabled,
+                    limit_load_slot_count_from_snapshot,
+                    verify_storage_hashes,
+                ),
+            }?)
+        })?;
 
     let status_cache_path = unpacked_snapshots_dir.join(SNAPSHOT_STATUS_CACHE_FILE_NAME);
     let slot_deltas = deserialize_snapshot_data_file(&status_cache_path, |stream| {
@@ -852,7 +1109,7 @@ fn rebuild_bank_from_snapshots(
     bank.src.append(&slot_deltas);
 
     info!("Loaded bank for slot: {}", bank.slot());
-    Ok(bank)
+    Ok((bank, generate_index_report))
 }
 
 fn get_snapshot_file_name(slot: Slot) -> String {
@@ -920,6 +1177,14 @@ pub fn snapshot_bank(
     archive_format: &ArchiveFormat,
     hash_for_testing: Option<Hash>,
 ) -> Result<()> {
+    // before the window defers shrinking the slots
+    root_bank
+        .rc
+        .accounts
+        .accounts_db
+        .expand_data_refs(root_bank.slot());
+    let snapshot_window =
+        AccountsDb::open_snapshot_window(&root_bank.rc.accounts.accounts_db, root_bank.slot());
     let storages: Vec<_> = root_bank.get_snapshot_storages();
     let mut add_snapshot_time = Measure::start("add-snapshot-ms");
     add_snapshot(snapshot_path, &root_bank, &storages, snapshot_version)?;
@@ -932,7 +1197,7 @@ pub fn snapshot_bank(
         .last()
         .expect("no snapshots found in config snapshot_path");
 
-    let package = package_snapshot(
+    let mut package = package_snapshot(
         &root_bank,
         latest_slot_snapshot_paths,
         snapshot_path,
@@ -943,6 +1208,7 @@ pub fn snapshot_bank(
         snapshot_version,
         hash_for_testing,
     )?;
+    package.snapshot_window = Some(snapshot_window);
 
     accounts_package_sender.send(package)?;
 
@@ -971,6 +1237,9 @@ pub fn bank_to_snapshot_archive<P: AsRef<Path>, Q: AsRef<Path>>(
 
     let temp_dir = tempfile::tempdir_in(snapshot_path)?;
 
+    bank.rc.accounts.accounts_db.expand_data_refs(bank.slot());
+    let _snapshot_window =
+        AccountsDb::open_snapshot_window(&bank.rc.accounts.accounts_db, bank.slot());
     let storages: Vec<_> = bank.get_snapshot_storages();
     let slot_snapshot_paths = add_snapshot(&temp_dir, &bank, &storages, snapshot_version)?;
     let package = package_snapshot(
@@ -1021,7 +1290,7 @@ pub fn process_accounts_package_pre(
         accounts_package.archive_format,
     );
 
-    AccountsPackage::new(
+    let mut package = AccountsPackage::new(
         accounts_package.slot,
         accounts_package.block_height,
         accounts_package.slot_deltas,
@@ -1031,7 +1300,9 @@ pub fn process_accounts_package_pre(
         hash,
         accounts_package.archive_format,
         accounts_package.snapshot_version,
-    )
+    );
+    package.snapshot_window = accounts_package.snapshot_window;
+    package
 }
 
 #[cfg(test)]
@@ -1041,6 +1312,127 @@ mod tests {
     use bincode::{deserialize_from, serialize_into};
     use std::mem::size_of;
 
+    #[test]
+    fn test_storage_manifest() {
+        let accounts_dir = tempfile::TempDir::new().unwrap();
+        let mut unpacked_append_vec_map = UnpackedAppendVecMap::new();
+        for (file_name, contents) in &[("0.1", b"first"), ("1.2", b"other")] {
+            let path = accounts_dir.path().join(file_name);
+            fs::write(&path, contents).unwrap();
+            unpacked_append_vec_map.insert(file_name.to_string(), path);
+        }
+        let bank_file = accounts_dir.path().join("bank");
+        fs::write(&bank_file, b"bank").unwrap();
+        unpacked_append_vec_map.insert(StorageManifest::bank_file_name(2), bank_file);
+        let keypair = solana_sdk::signature::Keypair::new();
+        let signer = keypair.pubkey();
+        let mut manifest = StorageManifest::default();
+        for (file_name, path) in &unpacked_append_vec_map {
+            manifest
+                .add_file(file_name.clone(), path, &keypair)
+                .unwrap();
+        }
+        manifest.verify(&signer, &unpacked_append_vec_map).unwrap();
+        let manifest_path = accounts_dir.path().join("manifest");
+        manifest.save(&manifest_path).unwrap();
+        assert_eq!(StorageManifest::load(&manifest_path).unwrap(), manifest);
+
+        let manifest_error =
+            |manifest: &StorageManifest| match manifest.verify(&signer, &unpacked_append_vec_map) {
+                Err(SnapshotError::StorageManifest(err)) => err,
+                result => panic!("unexpected {:?}", result),
+            };
+        // the signer is the one trusted, not whoever signed the manifest
+        let mut resigned = StorageManifest::default();
+        for (file_name, path) in &unpacked_append_vec_map {
+            resigned
+                .add_file(
+                    file_name.clone(),
+                    path,
+                    &solana_sdk::signature::Keypair::new(),
+                )
+                .unwrap();
+        }
+        assert!(matches!(
+            manifest_error(&resigned),
+            StorageManifestError::InvalidSignature(_)
+        ));
+        let mut unsigned = manifest.clone();
+        unsigned.entries.get_mut("1.2").unwrap().signature = Signature::default();
+        assert_eq!(
+            manifest_error(&unsigned),
+            StorageManifestError::InvalidSignature("1.2".to_string())
+        );
+        let mut incomplete = manifest.clone();
+        incomplete.entries.remove("0.1");
+        assert_eq!(
+            manifest_error(&incomplete),
+            StorageManifestError::NotInManifest("0.1".to_string())
+        );
+        let mut unpacked_subset = unpacked_append_vec_map.clone();
+        unpacked_subset.remove(&StorageManifest::bank_file_name(2));
+        assert!(matches!(
+            manifest.verify(&signer, &unpacked_subset),
+            Err(SnapshotError::StorageManifest(StorageManifestError::MissingFile(file_name)))
+                if file_name == StorageManifest::bank_file_name(2)
+        ));
+
+        // a signed entry doesn't vouch for a file of another name with the same contents
+        let mut swapped = manifest.clone();
+        fs::write(&unpacked_append_vec_map["1.2"], b"first").unwrap();
+        let entry = swapped.entries["0.1"].clone();
+        swapped.entries.insert("1.2".to_string(), entry);
+        assert_eq!(
+            manifest_error(&swapped),
+            StorageManifestError::InvalidSignature("1.2".to_string())
+        );
+
+        let expected = manifest.entries["1.2"].digest;
+        fs::write(&unpacked_append_vec_map["1.2"], b"tampered").unwrap();
+        assert_eq!(
+            manifest_error(&manifest),
+            StorageManifestError::DigestMismatch {
+                file_name: "1.2".to_string(),
+                expected,
+                actual: solana_sdk::hash::hash(b"tampered"),
+            }
+        );
+    }
+
+    #[test]
+    fn test_link_or_copy_append_vecs() {
+        let unpacked_dir = tempfile::TempDir::new().unwrap();
+        let unpacked_accounts_dir = unpacked_dir.path().join("accounts");
+        fs::create_dir_all(&unpacked_accounts_dir).unwrap();
+        let file_names = vec!["0.1".to_string(), "1.2".to_string()];
+        for file_name in &file_names {
+            fs::write(unpacked_accounts_dir.join(file_name), file_name).unwrap();
+        }
+        let account_dirs: Vec<_> = (0..2)
+            .map(|_| tempfile::TempDir::new_in(unpacked_dir.path()).unwrap())
+            .collect();
+        let account_paths: Vec<_> = account_dirs
+            .iter()
+            .map(|dir| dir.path().to_path_buf())
+            .collect();
+
+        let (unpacked_append_vec_map, linked_append_vecs) =
+            link_or_copy_append_vecs(&unpacked_accounts_dir, &account_paths).unwrap();
+        assert_eq!(unpacked_append_vec_map.len(), 2);
+        // everything lives on the same filesystem here
+        assert_eq!(linked_append_vecs, file_names.iter().cloned().collect());
+        for file_name in &file_names {
+            let path = &unpacked_append_vec_map[file_name];
+            assert!(account_paths
+                .iter()
+                .any(|account_path| path == &account_path.join("accounts").join(file_name)));
+            assert_eq!(&fs::read_to_string(path).unwrap(), file_name);
+            // removing the link leaves the unpacked snapshot intact
+            fs::remove_file(path).unwrap();
+            assert!(unpacked_accounts_dir.join(file_name).is_file());
+        }
+    }
+
     #[test]
     fn test_serialize_snapshot_data_file_under_limit() {
         let temp_dir = tempfile::TempDir::new().unwrap();
@@ -0,0 +1,201 @@
+//! Bloom filters of the pubkeys in a storage, so that finding the storages that may hold an
+//! account doesn't take reading every one of them. A filter is kept in a file next to the
+//! storage's, named like it with a `.bloom` suffix, which `AppendVec::parse_file_name()`
+//! doesn't take for a storage.
+//!
+//! A filter only covers the accounts the storage held when it was built, and records which
+//! storage that was, so a filter left behind by a storage since reset or rewritten is
+//! recognized as stale rather than trusted to rule out accounts.
+
+use crate::{
+    append_vec::{AppendVec, StoredMetaWriteVersion},
+    bloom::Bloom,
+};
+use serde::{Deserialize, Serialize};
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+const FALSE_POSITIVE_RATE: f64 = 0.01;
+// about the pubkeys of a full default sized storage of empty accounts
+const MAX_BITS: usize = 8 * 1024 * 1024;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StorageBloom {
+    slot: Slot,
+    id: usize,
+    /// Write version of the first account, telling a recycled storage apart
+    first_write_version: Option<StoredMetaWriteVersion>,
+    /// Length of the storage covered by the filter
+    len: usize,
+    bloom: Bloom<Pubkey>,
+}
+
+impl StorageBloom {
+    /// Filter of every account in `accounts`, the storage `id` of `slot`
+    pub fn build(slot: Slot, id: usize, accounts: &AppendVec) -> Self {
+        // whatever is appended meanwhile is added too, but not counted as covered
+        let len = accounts.len();
+        let stored: Vec<_> = accounts.accounts(0);
+        let mut bloom = Bloom::random(stored.len(), FALSE_POSITIVE_RATE, MAX_BITS);
+        for account in &stored {
+            bloom.add(&account.meta.pubkey);
+        }
+        Self {
+            slot,
+            id,
+            first_write_version: stored.first().map(|account| account.meta.write_version),
+            len,
+            bloom,
+        }
+    }
+
+    /// Where the filter of the storage at `storage_path` is kept
+    pub fn path(storage_path: &Path) -> PathBuf {
+        let mut file_name = storage_path.file_name().unwrap_or_default().to_owned();
+        file_name.push(".bloom");
+        storage_path.with_file_name(file_name)
+    }
+
+    pub fn save(&self, storage_path: &Path) -> io::Result<()> {
+        let path = Self::path(storage_path);
+        // a partially written filter must not be mistaken for one
+        let tmp_path = path.with_extension("bloom.tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        bincode::serialize_into(&mut writer, self)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp_path, &path)
+    }
+
+    /// The filter kept for the storage at `storage_path`, if there is one
+    pub fn load(storage_path: &Path) -> io::Result<Option<Self>> {
+        let file = match File::open(Self::path(storage_path)) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        bincode::deserialize_from(BufReader::new(file))
+            .map(Some)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    pub fn remove(storage_path: &Path) {
+        let _ = fs::remove_file(Self::path(storage_path));
+    }
+
+    /// Whether the filter was built from the storage `id` of `slot` as `accounts` now holds
+    /// it, or from fewer of its accounts
+    pub fn covers(&self, slot: Slot, id: usize, accounts: &AppendVec) -> bool {
+        self.slot == slot
+            && self.id == id
+            && self.len <= accounts.len()
+            && self.first_write_version
+                == accounts
+                    .get_account(0)
+                    .map(|(account, _)| account.meta.write_version)
+    }
+
+    /// False if `pubkey` isn't among the accounts the filter was built from. Accounts
+    /// appended to the storage since can't be ruled out, see `len()`.
+    pub fn maybe_contains(&self, pubkey: &Pubkey) -> bool {
+        self.bloom.contains(pubkey)
+    }
+
+    /// Whether `pubkey` is known not to be in `accounts`, the storage `id` of `slot`: the
+    /// filter must have been built from it, with nothing appended since
+    pub fn rules_out(&self, slot: Slot, id: usize, accounts: &AppendVec, pubkey: &Pubkey) -> bool {
+        self.len == accounts.len()
+            && self.covers(slot, id, accounts)
+            && !self.maybe_contains(pubkey)
+    }
+
+    /// The length of the storage covered by the filter
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::append_vec::{test_utils::get_append_vec_path, StoredMeta};
+    use solana_sdk::account::AccountSharedData;
+
+    fn append(accounts: &AppendVec, pubkey: &Pubkey, write_version: StoredMetaWriteVersion) {
+        let meta = StoredMeta {
+            write_version,
+            pubkey: *pubkey,
+            data_len: 0,
+        };
+        let account = AccountSharedData::new(1, 0, &Pubkey::default());
+        accounts
+            .append_account(meta, &account, solana_sdk::hash::Hash::default())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_storage_bloom() {
+        let file = get_append_vec_path("test_storage_bloom");
+        let accounts = AppendVec::new(&file.path, true, 1024 * 1024);
+        let pubkeys: Vec<_> = (0..100).map(|_| solana_sdk::pubkey::new_rand()).collect();
+        for (i, pubkey) in pubkeys.iter().enumerate() {
+            append(&accounts, pubkey, i as u64 + 1);
+        }
+
+        let bloom = StorageBloom::build(5, 7, &accounts);
+        assert_eq!(bloom.len(), accounts.len());
+        assert!(pubkeys.iter().all(|pubkey| bloom.maybe_contains(pubkey)));
+        let false_positives = (0..1000)
+            .filter(|_| bloom.maybe_contains(&solana_sdk::pubkey::new_rand()))
+            .count();
+        assert!(false_positives < 100, "{}", false_positives);
+        assert!(bloom.covers(5, 7, &accounts));
+        assert!(!bloom.covers(5, 8, &accounts));
+
+        assert!(StorageBloom::load(&file.path).unwrap().is_none());
+        bloom.save(&file.path).unwrap();
+        let loaded = StorageBloom::load(&file.path).unwrap().unwrap();
+        assert!(loaded.covers(5, 7, &accounts));
+        assert!(pubkeys.iter().all(|pubkey| loaded.maybe_contains(pubkey)));
+        // it isn't taken for a storage file
+        assert_eq!(
+            AppendVec::parse_file_name(
+                StorageBloom::path(&file.path)
+                    .file_name()
+                    .unwrap()
+                    .to_str()
+                    .unwrap()
+            ),
+            None
+        );
+
+        let other = solana_sdk::pubkey::new_rand();
+        assert_eq!(
+            loaded.rules_out(5, 7, &accounts, &other),
+            !loaded.maybe_contains(&other)
+        );
+        assert!(!loaded.rules_out(5, 7, &accounts, &pubkeys[0]));
+        assert!(!loaded.rules_out(5, 8, &accounts, &other));
+
+        // appending keeps it valid for what it covers, but rules nothing out anymore
+        append(&accounts, &solana_sdk::pubkey::new_rand(), 101);
+        assert!(loaded.covers(5, 7, &accounts));
+        assert!(!loaded.rules_out(5, 7, &accounts, &other));
+        // but not once the storage is written anew
+        accounts.reset();
+        append(&accounts, &solana_sdk::pubkey::new_rand(), 102);
+        assert!(!loaded.covers(5, 7, &accounts));
+
+        StorageBloom::remove(&file.path);
+        assert!(StorageBloom::load(&file.path).unwrap().is_none());
+    }
+}
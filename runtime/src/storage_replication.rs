@@ -0,0 +1,438 @@
+//! Primitives for replicating account storages, e.g. from a validator to RPC replicas. A
+//! `StorageServer` pins storage entries and serves them in fixed size chunks, described by a
+//! manifest holding the hash of every chunk. `fetch_storage()` pulls a storage through any
+//! `ReplicationTransport`, checks every chunk against the manifest and the reassembled file's
+//! layout, and opens it as a storage entry. Moving requests and responses between processes
+//! is up to the transport; both serialize with bincode.
+use crate::{
+    accounts_db::{AccountStorageEntry, AppendVecId},
+    append_vec::AppendVec,
+};
+use solana_sdk::{
+    clock::Slot,
+    hash::{hashv, Hash},
+};
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use thiserror::Error;
+
+pub const DEFAULT_REPLICATION_CHUNK_SIZE: usize = 1024 * 1024;
+const MAX_REPLICATED_STORAGE_LEN: usize = 16 * 1024 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum ReplicationError {
+    #[error("storage {id} of slot {slot} is not served")]
+    UnknownStorage { slot: Slot, id: AppendVecId },
+
+    #[error("chunk {index} is out of range for storage {id} of slot {slot}")]
+    ChunkOutOfRange {
+        slot: Slot,
+        id: AppendVecId,
+        index: usize,
+    },
+
+    #[error("chunk {index} of storage {id} of slot {slot} doesn't match its manifest")]
+    ChunkMismatch {
+        slot: Slot,
+        id: AppendVecId,
+        index: usize,
+    },
+
+    #[error("invalid manifest: {0}")]
+    InvalidManifest(String),
+
+    #[error("unexpected response to {0}")]
+    UnexpectedResponse(&'static str),
+
+    #[error("transport error: {0}")]
+    Transport(String),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Describes a served storage: its written length and the hash of every chunk of it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StorageManifest {
+    pub slot: Slot,
+    pub id: AppendVecId,
+    pub len: usize,
+    pub chunk_size: usize,
+    pub chunk_hashes: Vec<Hash>,
+}
+
+impl StorageManifest {
+    pub fn chunk_hash(index: usize, data: &[u8]) -> Hash {
+        hashv(&[&(index as u64).to_le_bytes(), data])
+    }
+
+    /// Byte range of chunk `index`, if there is such a chunk
+    pub fn chunk_range(&self, index: usize) -> Option<std::ops::Range<usize>> {
+        let start = index.checked_mul(self.chunk_size)?;
+        (start < self.len).then(|| start..std::cmp::min(start + self.chunk_size, self.len))
+    }
+
+    fn sanitize(&self, slot: Slot, id: AppendVecId) -> Result<(), ReplicationError> {
+        let invalid = |reason: String| Err(ReplicationError::InvalidManifest(reason));
+        if (self.slot, self.id) != (slot, id) {
+            return invalid(format!(
+                "describes storage {} of slot {} instead of storage {} of slot {}",
+                self.id, self.slot, id, slot
+            ));
+        }
+        if self.len == 0 || self.len > MAX_REPLICATED_STORAGE_LEN {
+            return invalid(format!("length {}", self.len));
+        }
+        if self.chunk_size == 0 {
+            return invalid("zero chunk size".to_string());
+        }
+        let num_chunks = (self.len + self.chunk_size - 1) / self.chunk_size;
+        if self.chunk_hashes.len() != num_chunks {
+            return invalid(format!(
+                "{} chunk hashes for {} chunks",
+                self.chunk_hashes.len(),
+                num_chunks
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplicationRequest {
+    /// The storages served for `slot`
+    ListStorages {
+        slot: Slot,
+    },
+    Manifest {
+        slot: Slot,
+        id: AppendVecId,
+    },
+    Chunk {
+        slot: Slot,
+        id: AppendVecId,
+        index: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReplicationResponse {
+    Storages(Vec<AppendVecId>),
+    Manifest(StorageManifest),
+    Chunk(Vec<u8>),
+}
+
+/// Carries a request to a `StorageServer` and its response back. Errors the server returned
+/// should come back as they are; failures of the transport itself as `Transport`.
+pub trait ReplicationTransport {
+    fn request(
+        &self,
+        request: &ReplicationRequest,
+    ) -> Result<ReplicationResponse, ReplicationError>;
+}
+
+/// Serves the storages it was given. Holding them keeps them from being recycled, and their
+/// files from being removed, for as long as the server lives.
+#[derive(Debug)]
+pub struct StorageServer {
+    storages: HashMap<(Slot, AppendVecId), Arc<AccountStorageEntry>>,
+    chunk_size: usize,
+    // computed on first request, so every chunk is served from the length hashed there
+    manifests: Mutex<HashMap<(Slot, AppendVecId), Arc<StorageManifest>>>,
+}
+
+impl StorageServer {
+    /// Serves `storages`, e.g. those of `AccountsDb::get_snapshot_storages()`. Empty ones are
+    /// left out, they have nothing to replicate.
+    pub fn new(
+        storages: impl IntoIterator<Item = Arc<AccountStorageEntry>>,
+        chunk_size: usize,
+    ) -> Self {
+        assert!(chunk_size > 0);
+        Self {
+            storages: storages
+                .into_iter()
+                .filter(|storage| !storage.accounts.is_empty())
+                .map(|storage| ((storage.slot(), storage.append_vec_id()), storage))
+                .collect(),
+            chunk_size,
+            manifests: Mutex::default(),
+        }
+    }
+
+    pub fn serve(
+        &self,
+        request: &ReplicationRequest,
+    ) -> Result<ReplicationResponse, ReplicationError> {
+        match *request {
+            ReplicationRequest::ListStorages { slot } => {
+                let mut ids: Vec<_> = self
+                    .storages
+                    .keys()
+                    .filter(|(storage_slot, _id)| *storage_slot == slot)
+                    .map(|(_slot, id)| *id)
+                    .collect();
+                ids.sort_unstable();
+                Ok(ReplicationResponse::Storages(ids))
+            }
+            ReplicationRequest::Manifest { slot, id } => Ok(ReplicationResponse::Manifest(
+                self.manifest(slot, id)?.as_ref().clone(),
+            )),
+            ReplicationRequest::Chunk { slot, id, index } => {
+                let manifest = self.manifest(slot, id)?;
+                let range = manifest
+                    .chunk_range(index)
+                    .ok_or(ReplicationError::ChunkOutOfRange { slot, id, index })?;
+                Ok(ReplicationResponse::Chunk(
+                    self.read(slot, id, range.start, range.len())?.to_vec(),
+                ))
+            }
+        }
+    }
+
+    fn manifest(
+        &self,
+        slot: Slot,
+        id: AppendVecId,
+    ) -> Result<Arc<StorageManifest>, ReplicationError> {
+        if let Some(manifest) = self.manifests.lock().unwrap().get(&(slot, id)) {
+            return Ok(manifest.clone());
+        }
+        let storage = self
+            .storages
+            .get(&(slot, id))
+            .ok_or(ReplicationError::UnknownStorage { slot, id })?;
+        let len = storage.accounts.len();
+        let chunk_hashes = (0..(len + self.chunk_size - 1) / self.chunk_size)
+            .map(|index| {
+                let start = index * self.chunk_size;
+                let data =
+                    self.read(slot, id, start, std::cmp::min(self.chunk_size, len - start))?;
+                Ok(StorageManifest::chunk_hash(index, data))
+            })
+            .collect::<Result<_, ReplicationError>>()?;
+        let manifest = Arc::new(StorageManifest {
+            slot,
+            id,
+            len,
+            chunk_size: self.chunk_size,
+            chunk_hashes,
+        });
+        Ok(self
+            .manifests
+            .lock()
+            .unwrap()
+            .entry((slot, id))
+            .or_insert(manifest)
+            .clone())
+    }
+
+    fn read(
+        &self,
+        slot: Slot,
+        id: AppendVecId,
+        offset: usize,
+        len: usize,
+    ) -> Result<&[u8], ReplicationError> {
+        self.storages
+            .get(&(slot, id))
+            .and_then(|storage| storage.accounts.written_bytes(offset, len))
+            .ok_or(ReplicationError::UnknownStorage { slot, id })
+    }
+}
+
+/// Serves in process, mostly useful to test clients
+impl ReplicationTransport for StorageServer {
+    fn request(
+        &self,
+        request: &ReplicationRequest,
+    ) -> Result<ReplicationResponse, ReplicationError> {
+        self.serve(request)
+    }
+}
+
+/// The storages served for `slot`
+pub fn list_storages(
+    transport: &impl ReplicationTransport,
+    slot: Slot,
+) -> Result<Vec<AppendVecId>, ReplicationError> {
+    match transport.request(&ReplicationRequest::ListStorages { slot })? {
+        ReplicationResponse::Storages(ids) => Ok(ids),
+        _ => Err(ReplicationError::UnexpectedResponse("ListStorages")),
+    }
+}
+
+/// Fetches storage `id` of `slot` into a file named like the original in `dir`, verifying
+/// every chunk against the manifest and the accounts' layout once reassembled. The entry keeps
+/// the id it had on the server, so callers merging it into an `AccountsDb` must remap ids that
+/// clash, as for storages unpacked from a snapshot.
+pub fn fetch_storage(
+    transport: &impl ReplicationTransport,
+    slot: Slot,
+    id: AppendVecId,
+    dir: &Path,
+) -> Result<AccountStorageEntry, ReplicationError> {
+    let manifest = match transport.request(&ReplicationRequest::Manifest { slot, id })? {
+        ReplicationResponse::Manifest(manifest) => manifest,
+        _ => return Err(ReplicationError::UnexpectedResponse("Manifest")),
+    };
+    manifest.sanitize(slot, id)?;
+
+    let path = dir.join(AppendVec::file_name(slot, id));
+    let result = (|| {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        for (index, expected_hash) in manifest.chunk_hashes.iter().enumerate() {
+            let data = match transport.request(&ReplicationRequest::Chunk { slot, id, index })? {
+                ReplicationResponse::Chunk(data) => data,
+                _ => return Err(ReplicationError::UnexpectedResponse("Chunk")),
+            };
+            let expected_len = manifest.chunk_range(index).unwrap().len();
+            if data.len() != expected_len
+                || StorageManifest::chunk_hash(index, &data) != *expected_hash
+            {
+                return Err(ReplicationError::ChunkMismatch { slot, id, index });
+            }
+            file.write_all(&data)?;
+        }
+        file.sync_all()?;
+        drop(file);
+        let (accounts, num_accounts) = AppendVec::new_from_file(&path, manifest.len)?;
+        Ok(AccountStorageEntry::new_existing(
+            slot,
+            id,
+            accounts,
+            num_accounts,
+        ))
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&path);
+    }
+    result
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::accounts_db::AccountsDb;
+    use solana_sdk::{
+        account::{AccountSharedData, ReadableAccount},
+        pubkey::Pubkey,
+    };
+    use tempfile::TempDir;
+
+    /// Flips a byte of every chunk it carries
+    struct CorruptingTransport<'a>(&'a StorageServer);
+
+    impl ReplicationTransport for CorruptingTransport<'_> {
+        fn request(
+            &self,
+            request: &ReplicationRequest,
+        ) -> Result<ReplicationResponse, ReplicationError> {
+            match self.0.serve(request)? {
+                ReplicationResponse::Chunk(mut data) => {
+                    data[0] ^= 1;
+                    Ok(ReplicationResponse::Chunk(data))
+                }
+                response => Ok(response),
+            }
+        }
+    }
+
+    #[test]
+    fn test_fetch_storage() {
+        let db = AccountsDb::new_single();
+        let accounts: Vec<_> = (0..20)
+            .map(|i| {
+                (
+                    Pubkey::new_unique(),
+                    AccountSharedData::new(i + 1, i as usize * 10, &Pubkey::default()),
+                )
+            })
+            .collect();
+        let to_store: Vec<_> = accounts
+            .iter()
+            .map(|(pubkey, account)| (pubkey, account))
+            .collect();
+        db.store_uncached(1, &to_store);
+        db.add_root(1);
+        let storages = db.get_snapshot_storages(1);
+        let served_id = storages[0][0].append_vec_id();
+        // small chunks, so the storage spans several
+        let server = StorageServer::new(storages.into_iter().flatten(), 512);
+
+        assert_eq!(list_storages(&server, 1).unwrap(), vec![served_id]);
+        assert!(list_storages(&server, 2).unwrap().is_empty());
+        assert!(matches!(
+            fetch_storage(&server, 2, served_id, Path::new("/nonexistent")),
+            Err(ReplicationError::UnknownStorage { .. })
+        ));
+
+        let dir = TempDir::new().unwrap();
+        assert!(matches!(
+            fetch_storage(&CorruptingTransport(&server), 1, served_id, dir.path()),
+            Err(ReplicationError::ChunkMismatch { index: 0, .. })
+        ));
+        // the partial file is gone
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+
+        let fetched = fetch_storage(&server, 1, served_id, dir.path()).unwrap();
+        assert_eq!(fetched.slot(), 1);
+        assert_eq!(fetched.append_vec_id(), served_id);
+        let mut fetched_accounts: Vec<_> = fetched
+            .all_accounts()
+            .into_iter()
+            .map(|stored| (stored.meta.pubkey, stored.account_meta.lamports))
+            .collect();
+        fetched_accounts.sort_unstable();
+        let mut expected: Vec<_> = accounts
+            .iter()
+            .map(|(pubkey, account)| (*pubkey, account.lamports()))
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(fetched_accounts, expected);
+    }
+
+    #[test]
+    fn test_manifest_sanitize() {
+        let manifest = StorageManifest {
+            slot: 1,
+            id: 2,
+            len: 1000,
+            chunk_size: 512,
+            chunk_hashes: vec![Hash::default(); 2],
+        };
+        assert!(manifest.sanitize(1, 2).is_ok());
+        assert!(manifest.sanitize(1, 3).is_err());
+        assert_eq!(manifest.chunk_range(1), Some(512..1000));
+        assert_eq!(manifest.chunk_range(2), None);
+        for bad in vec![
+            StorageManifest {
+                len: 0,
+                ..manifest.clone()
+            },
+            StorageManifest {
+                chunk_size: 0,
+                ..manifest.clone()
+            },
+            StorageManifest {
+                chunk_hashes: vec![Hash::default(); 3],
+                ..manifest.clone()
+            },
+        ]
+        .into_iter()
+        {
+            assert!(matches!(
+                bad.sanitize(1, 2),
+                Err(ReplicationError::InvalidManifest(_))
+            ));
+        }
+    }
+}
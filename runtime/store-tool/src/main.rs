@@ -1,6 +1,8 @@
-use clap::{crate_description, crate_name, value_t_or_exit, App, Arg};
+use clap::{crate_description, crate_name, value_t, value_t_or_exit, App, Arg};
 use log::*;
-use solana_runtime::append_vec::AppendVec;
+use solana_runtime::{append_vec::AppendVec, storage_bloom::StorageBloom};
+use solana_sdk::pubkey::Pubkey;
+use std::path::Path;
 
 fn main() {
     solana_logger::setup_with_default("solana=info");
@@ -21,11 +23,19 @@ fn main() {
                 .value_name("LEN")
                 .help("len of store to open"),
         )
+        .arg(
+            Arg::with_name("pubkey")
+                .long("pubkey")
+                .takes_value(true)
+                .value_name("PUBKEY")
+                .help("only show the versions of this account"),
+        )
         .get_matches();
 
     let file = value_t_or_exit!(matches, "file", String);
     let len = value_t_or_exit!(matches, "len", usize);
-    let (mut store, num_accounts) = AppendVec::new_from_file(file, len).expect("should succeed");
+    let pubkey = value_t!(matches, "pubkey", Pubkey).ok();
+    let (store, num_accounts) = AppendVec::new_from_file(&file, len).expect("should succeed");
     store.set_no_remove_on_drop();
     info!(
         "store: len: {} capacity: {} accounts: {}",
@@ -33,10 +43,30 @@ fn main() {
         store.capacity(),
         num_accounts,
     );
-    for account in store.accounts(0) {
+    if let Some(pubkey) = &pubkey {
+        let path = Path::new(&file);
+        let slot_and_id = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(AppendVec::parse_file_name);
+        if let (Some((slot, id)), Ok(Some(bloom))) = (slot_and_id, StorageBloom::load(path)) {
+            if bloom.rules_out(slot, id, &store, pubkey) {
+                info!("  no version of {} per the bloom filter", pubkey);
+                return;
+            }
+        }
+    }
+    for account in store
+        .accounts(0)
+        .into_iter()
+        .filter(|account| pubkey.map_or(true, |pubkey| account.meta.pubkey == pubkey))
+    {
         info!(
             "  account: {:?} version: {} data: {} hash: {:?}",
-            account.meta.pubkey, account.meta.write_version, account.meta.data_len, account.hash
+            account.meta.pubkey,
+            account.meta.write_version,
+            account.meta.data_len(),
+            account.hash
         );
     }
 }
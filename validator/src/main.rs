@@ -45,7 +45,9 @@ use {
         },
         bank_forks::{ArchiveFormat, SnapshotConfig, SnapshotVersion},
         hardened_unpack::MAX_GENESIS_ARCHIVE_UNPACKED_SIZE,
-        snapshot_utils::{get_highest_snapshot_archive_path, DEFAULT_MAX_SNAPSHOTS_TO_RETAIN},
+        snapshot_utils::{
+            get_highest_snapshot_archive_path, StorageManifest, DEFAULT_MAX_SNAPSHOTS_TO_RETAIN,
+        },
     },
     solana_sdk::{
         clock::{Slot, DEFAULT_S_PER_SLOT},
@@ -1332,6 +1334,25 @@ pub fn main() {
                 .help("The maximum number of times to abort and retry when encountering a \
                       slow snapshot download."),
         )
+        .arg(
+            Arg::with_name("snapshot_storage_manifest")
+                .long("snapshot-storage-manifest")
+                .value_name("PATH")
+                .takes_value(true)
+                .requires("snapshot_storage_manifest_signer")
+                .help("Check the files of the snapshot booted from against this manifest \
+                      of their digests"),
+        )
+        .arg(
+            Arg::with_name("snapshot_storage_manifest_signer")
+                .long("snapshot-storage-manifest-signer")
+                .value_name("PUBKEY")
+                .takes_value(true)
+                .validator(is_pubkey)
+                .requires("snapshot_storage_manifest")
+                .help("The trusted key every entry of the snapshot storage manifest must be \
+                      signed by"),
+        )
         .arg(
             Arg::with_name("contact_debug_interval")
                 .long("contact-debug-interval")
@@ -2043,6 +2064,15 @@ pub fn main() {
         None
     };
 
+    let snapshot_storage_manifest = matches.value_of("snapshot_storage_manifest").map(|path| {
+        let storage_manifest = StorageManifest::load(Path::new(path)).unwrap_or_else(|err| {
+            eprintln!("Unable to load snapshot storage manifest {}: {}", path, err);
+            exit(1);
+        });
+        let trusted_signer = pubkey_of(&matches, "snapshot_storage_manifest_signer").unwrap();
+        (storage_manifest, trusted_signer)
+    });
+
     let trusted_validators = validators_set(
         &identity_keypair.pubkey(),
         &matches,
@@ -2173,6 +2203,7 @@ pub fn main() {
         accounts_db_use_index_hash_calculation: matches.is_present("accounts_db_index_hashing"),
         tpu_coalesce_ms,
         no_wait_for_vote_to_start_leader: matches.is_present("no_wait_for_vote_to_start_leader"),
+        snapshot_storage_manifest,
         ..ValidatorConfig::default()
     };
 